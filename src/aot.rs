@@ -0,0 +1,346 @@
+use crate::ast::{Expr, Stmt};
+use crate::parser::calc_parser;
+use crate::visitor::{CalculatorJIT, EvalError};
+use inkwell::context::Context;
+use inkwell::OptimizationLevel;
+use peg::error::ParseError;
+use peg::str::LineCol;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::hash::{BuildHasher, Hasher};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const C_KEYWORDS: &[&str] = &[
+    "auto", "bool", "break", "case", "char", "const", "continue", "default", "do", "double",
+    "else", "enum", "extern", "false", "float", "for", "goto", "if", "inline", "int", "long",
+    "register", "restrict", "return", "short", "signed", "sizeof", "static", "struct", "switch",
+    "true", "typedef", "union", "unsigned", "void", "volatile", "while",
+];
+
+/// Functions the generated code may call, exporting one of these names would
+/// shadow it.
+const LIBM_FUNCTIONS: &[&str] = &[
+    "ceil", "cos", "exp", "fabs", "floor", "fmax", "fmin", "fmod", "log", "log10", "log2", "pow",
+    "sin", "sqrt", "tan",
+];
+
+#[derive(Debug)]
+pub enum AotError {
+    Parse(ParseError<LineCol>),
+    Eval(Vec<EvalError>),
+    InvalidName(String),
+    Codegen(String),
+    Link(String),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for AotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AotError::Parse(e) => write!(f, "{e}"),
+            AotError::Eval(errors) => match errors.first() {
+                Some(e) => write!(f, "{e}"),
+                None => write!(f, "invalid expression"),
+            },
+            AotError::InvalidName(message) => write!(f, "{message}"),
+            AotError::Codegen(message) => write!(f, "code generation failed: {message}"),
+            AotError::Link(message) => write!(f, "linking failed: {message}"),
+            AotError::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for AotError {}
+
+impl From<std::io::Error> for AotError {
+    fn from(e: std::io::Error) -> Self {
+        AotError::Io(e)
+    }
+}
+
+/// Compiles the expression `source` into a function of `params` exported as
+/// `name`, and writes it to `output`. A path ending in `.o` gets the object
+/// file, any other path a shared library linked by the system C compiler
+/// (`$CC`, `cc` by default).
+pub fn compile(
+    source: &str,
+    params: &[&str],
+    name: &str,
+    output: &Path,
+    opt_level: OptimizationLevel,
+) -> Result<(), AotError> {
+    check_signature(name, params)?;
+    let program = calc_parser::program(source).map_err(AotError::Parse)?;
+    let ast = single_expr(&program)?;
+
+    let context = Context::create();
+    let mut calculator_jit = CalculatorJIT::new(&context);
+    calculator_jit.preset().unwrap();
+    calculator_jit
+        .check_formula(ast, params)
+        .map_err(AotError::Eval)?;
+
+    if output.extension().is_some_and(|ext| ext == "o") {
+        return calculator_jit
+            .write_object(ast, params, name, output, opt_level)
+            .map_err(AotError::Codegen);
+    }
+    let object = create_temp_object(name)?;
+    let linked = calculator_jit
+        .write_object(ast, params, name, &object, opt_level)
+        .map_err(AotError::Codegen)
+        .and_then(|()| link_shared(&object, output));
+    let _ = std::fs::remove_file(&object);
+    linked
+}
+
+/// Creates the empty file the object is written to before linking. The name
+/// is random and the file created exclusively, so that nobody sharing the
+/// temporary directory can plant a file or a link there beforehand.
+fn create_temp_object(name: &str) -> std::io::Result<PathBuf> {
+    loop {
+        let suffix = RandomState::new().build_hasher().finish();
+        let file_name = format!("rcalc-{}-{name}-{suffix:016x}.o", std::process::id());
+        let path = std::env::temp_dir().join(file_name);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(_) => return Ok(path),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// The C declaration of the function compiled by `compile`.
+pub fn header(name: &str, params: &[&str]) -> String {
+    let guard = format!("RCALC_{}_H", name.to_uppercase());
+    let params = if params.is_empty() {
+        "void".to_string()
+    } else {
+        params
+            .iter()
+            .map(|param| format!("double {param}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    format!(
+        "/* Generated by rcalc, do not edit. */\n\
+         #ifndef {guard}\n\
+         #define {guard}\n\
+         \n\
+         #ifdef __cplusplus\n\
+         extern \"C\" {{\n\
+         #endif\n\
+         \n\
+         double {name}({params});\n\
+         \n\
+         #ifdef __cplusplus\n\
+         }}\n\
+         #endif\n\
+         \n\
+         #endif /* {guard} */\n"
+    )
+}
+
+/// Path of the header next to `output`, `liblinear.so` gets `liblinear.h`.
+pub fn default_header_path(output: &Path) -> PathBuf {
+    output.with_extension("h")
+}
+
+/// Checks that `name` and `params` can be used as is in the header.
+fn check_signature(name: &str, params: &[&str]) -> Result<(), AotError> {
+    check_c_identifier(name, "function name")?;
+    if LIBM_FUNCTIONS.contains(&name) {
+        return Err(AotError::InvalidName(format!(
+            "function name `{name}` clashes with the C math library"
+        )));
+    }
+    for (i, param) in params.iter().enumerate() {
+        check_c_identifier(param, "parameter")?;
+        if params[..i].contains(param) {
+            return Err(AotError::InvalidName(format!(
+                "parameter `{param}` is given more than once"
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn check_c_identifier(name: &str, what: &str) -> Result<(), AotError> {
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(AotError::InvalidName(format!(
+            "{what} `{name}` is not a valid C identifier"
+        )));
+    }
+    if C_KEYWORDS.contains(&name) {
+        return Err(AotError::InvalidName(format!(
+            "{what} `{name}` is a C keyword"
+        )));
+    }
+    Ok(())
+}
+
+fn single_expr(program: &[Stmt]) -> Result<&Expr, AotError> {
+    match program {
+        [Stmt::Expr(e)] => Ok(e),
+        _ => Err(AotError::InvalidName(
+            "only a single expression can be compiled".into(),
+        )),
+    }
+}
+
+fn link_shared(object: &Path, output: &Path) -> Result<(), AotError> {
+    let cc = std::env::var_os("CC").unwrap_or_else(|| "cc".into());
+    let result = Command::new(&cc)
+        .arg("-shared")
+        .arg("-o")
+        .arg(output)
+        .arg(object)
+        .arg("-lm")
+        .output()
+        .map_err(|e| AotError::Link(format!("failed to run `{}`: {e}", cc.to_string_lossy())))?;
+    if !result.status.success() {
+        return Err(AotError::Link(
+            String::from_utf8_lossy(&result.stderr).trim().to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory for the files of one test.
+    fn scratch_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rcalc-aot-{}-{test}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Builds `main.c` against the given objects and libraries and returns
+    /// what the program prints.
+    fn run_harness(dir: &Path, main: &str, link_args: &[&str]) -> String {
+        std::fs::write(dir.join("main.c"), main).unwrap();
+        let status = Command::new("cc")
+            .current_dir(dir)
+            .args(["main.c", "-o", "main"])
+            .args(link_args)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let output = Command::new(dir.join("main")).output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn header_declaration() {
+        let header = header("linear", &["a", "x", "b"]);
+        assert!(header.contains("#ifndef RCALC_LINEAR_H\n#define RCALC_LINEAR_H\n"));
+        assert!(header.contains("\ndouble linear(double a, double x, double b);\n"));
+        assert!(header.contains("extern \"C\" {"));
+        assert!(super::header("answer", &[]).contains("double answer(void);"));
+        assert_eq!(
+            default_header_path(Path::new("out/liblinear.so")),
+            PathBuf::from("out/liblinear.h")
+        );
+    }
+
+    #[test]
+    fn invalid_signatures() {
+        let error = |name: &str, params: &[&str], source: &str| {
+            compile(
+                source,
+                params,
+                name,
+                Path::new("unused.o"),
+                OptimizationLevel::None,
+            )
+            .unwrap_err()
+        };
+        assert!(matches!(error("2f", &[], "1"), AotError::InvalidName(_)));
+        assert!(matches!(error("sqrt", &[], "1"), AotError::InvalidName(_)));
+        assert!(matches!(
+            error("f", &["int"], "1"),
+            AotError::InvalidName(_)
+        ));
+        assert!(matches!(
+            error("f", &["x", "x"], "x"),
+            AotError::InvalidName(_)
+        ));
+        assert!(matches!(
+            error("f", &[], "let x = 1"),
+            AotError::InvalidName(_)
+        ));
+        assert!(matches!(error("f", &[], "1 +"), AotError::Parse(_)));
+
+        let AotError::Eval(errors) = error("f", &["x"], "x + y * sqr(x)") else {
+            panic!("Expected unknown names");
+        };
+        let names: Vec<_> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(names, ["unknown variable `y`", "unknown function `sqr`"]);
+        assert!(matches!(error("f", &["x"], "sin(x, x)"), AotError::Eval(_)));
+    }
+
+    #[test]
+    fn object_file() {
+        let dir = scratch_dir("object");
+        compile(
+            "sqrt(x) + 3! + max(x, PI) + (x % 3)",
+            &["x"],
+            "f",
+            &dir.join("f.o"),
+            OptimizationLevel::Default,
+        )
+        .unwrap();
+        std::fs::write(dir.join("f.h"), header("f", &["x"])).unwrap();
+        let main = "#include <stdio.h>\n#include \"f.h\"\n\
+                    int main(void) { printf(\"%.17g\\n\", f(16.0)); return 0; }\n";
+        let printed = run_harness(&dir, main, &["f.o", "-lm"]);
+        assert_eq!(
+            printed.trim().parse::<f64>().unwrap(),
+            4.0 + 6.0 + 16.0 + 1.0
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn shared_library() {
+        let dir = scratch_dir("shared");
+        let params = ["a", "x", "b"];
+        compile(
+            "a*x+b",
+            &params,
+            "linear",
+            &dir.join("liblinear.so"),
+            OptimizationLevel::Default,
+        )
+        .unwrap();
+        std::fs::write(dir.join("liblinear.h"), header("linear", &params)).unwrap();
+        let main = "#include <stdio.h>\n#include \"liblinear.h\"\n\
+                    int main(void) { printf(\"%.17g\\n\", linear(2.0, 3.0, 1.0)); return 0; }\n";
+        let rpath = format!("-Wl,-rpath,{}", dir.display());
+        let printed = run_harness(&dir, main, &["-L.", "-llinear", &rpath]);
+        assert_eq!(printed.trim(), "7");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn temp_object() {
+        let first = create_temp_object("f").unwrap();
+        let second = create_temp_object("f").unwrap();
+        assert_ne!(first, second);
+        for path in [first, second] {
+            assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use super::Expr;
+use super::{Expr, Span};
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Atom {
@@ -9,6 +9,6 @@ pub enum Atom {
 #[allow(clippy::from_over_into)]
 impl Into<Expr> for Atom {
     fn into(self) -> Expr {
-        Expr::Atom(self)
+        Expr::Atom(self, Span::default())
     }
 }
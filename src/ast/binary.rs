@@ -1,4 +1,4 @@
-use super::Expr;
+use super::{Expr, Span};
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum BinaryOp {
@@ -6,6 +6,13 @@ pub enum BinaryOp {
     Sub,
     Mul,
     Div,
+    /// Floored modulo, the result takes the sign of the divisor
+    Mod,
+    /// Division rounded towards negative infinity
+    FloorDiv,
+    /// Euclidean remainder, the result is never negative
+    Rem,
+    Pow,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -13,11 +20,14 @@ pub struct BinaryArithmetic {
     pub op: BinaryOp,
     pub lhs: Expr,
     pub rhs: Expr,
+    pub span: Span,
 }
 
 impl BinaryArithmetic {
+    /// The span covers both operands.
     pub fn new(op: BinaryOp, lhs: Expr, rhs: Expr) -> Self {
-        BinaryArithmetic { op, lhs, rhs }
+        let span = Span::new(lhs.span().start, rhs.span().end);
+        BinaryArithmetic { op, lhs, rhs, span }
     }
 }
 
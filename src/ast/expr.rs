@@ -1,9 +1,30 @@
-use super::{Atom, BinaryArithmetic, FunctionCall, UnaryArithmetic};
+use super::{Atom, BinaryArithmetic, FunctionCall, Span, UnaryArithmetic};
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Expr {
     BinaryArithmetic(Box<BinaryArithmetic>),
     UnaryArithmetic(Box<UnaryArithmetic>),
-    Atom(Atom),
+    Atom(Atom, Span),
     FunctionCall(FunctionCall),
 }
+
+impl Expr {
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::BinaryArithmetic(ref b) => b.span,
+            Expr::UnaryArithmetic(ref u) => u.span,
+            Expr::Atom(_, span) => *span,
+            Expr::FunctionCall(ref f) => f.span,
+        }
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        match self {
+            Expr::BinaryArithmetic(ref mut b) => b.span = span,
+            Expr::UnaryArithmetic(ref mut u) => u.span = span,
+            Expr::Atom(_, ref mut s) => *s = span,
+            Expr::FunctionCall(ref mut f) => f.span = span,
+        }
+        self
+    }
+}
@@ -1,14 +1,24 @@
-use super::Expr;
+use super::{Expr, Span};
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct FunctionCall {
     pub name: String,
     pub args: Vec<Expr>,
+    pub span: Span,
 }
 
 impl FunctionCall {
     pub fn new(name: String, args: Vec<Expr>) -> Self {
-        FunctionCall { name, args }
+        FunctionCall {
+            name,
+            args,
+            span: Span::default(),
+        }
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = span;
+        self
     }
 }
 
@@ -18,3 +28,27 @@ impl Into<Expr> for FunctionCall {
         Expr::FunctionCall(self)
     }
 }
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct FunctionDef {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Expr,
+    pub span: Span,
+}
+
+impl FunctionDef {
+    pub fn new(name: String, params: Vec<String>, body: Expr) -> Self {
+        FunctionDef {
+            name,
+            params,
+            body,
+            span: Span::default(),
+        }
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = span;
+        self
+    }
+}
@@ -2,10 +2,14 @@ pub mod atom;
 pub mod binary;
 pub mod expr;
 pub mod function;
+pub mod span;
+pub mod stmt;
 pub mod unary;
 
 pub use atom::Atom;
 pub use binary::{BinaryArithmetic, BinaryOp};
 pub use expr::Expr;
-pub use function::FunctionCall;
+pub use function::{FunctionCall, FunctionDef};
+pub use span::Span;
+pub use stmt::{Assignment, Stmt};
 pub use unary::{UnaryArithmetic, UnaryOp};
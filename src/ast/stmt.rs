@@ -0,0 +1,41 @@
+use super::{Expr, FunctionDef, Span};
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Assignment {
+    pub name: String,
+    pub value: Expr,
+    pub span: Span,
+}
+
+impl Assignment {
+    pub fn new(name: String, value: Expr) -> Self {
+        Assignment {
+            name,
+            value,
+            span: Span::default(),
+        }
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = span;
+        self
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Stmt {
+    /// `let name = expr`, introduces a new binding
+    Let(Assignment),
+    /// `name = expr`, rebinds `name`, or binds it if it does not exist yet
+    Assign(Assignment),
+    /// `name(params) = expr`, defines a function
+    Function(FunctionDef),
+    Expr(Expr),
+}
+
+#[allow(clippy::from_over_into)]
+impl Into<Stmt> for Expr {
+    fn into(self) -> Stmt {
+        Stmt::Expr(self)
+    }
+}
@@ -1,4 +1,4 @@
-use super::Expr;
+use super::{Expr, Span};
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum UnaryOp {
@@ -11,11 +11,20 @@ pub enum UnaryOp {
 pub struct UnaryArithmetic {
     pub op: UnaryOp,
     pub value: Expr,
+    pub span: Span,
 }
 
 impl UnaryArithmetic {
+    /// The span covers the operand, use [`UnaryArithmetic::with_span`] to
+    /// extend it over the operator.
     pub fn new(op: UnaryOp, value: Expr) -> Self {
-        UnaryArithmetic { op, value }
+        let span = value.span();
+        UnaryArithmetic { op, value, span }
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = span;
+        self
     }
 }
 
@@ -0,0 +1,160 @@
+use crate::cli::{OnError, Output};
+use crate::output::{Evaluation, Failure};
+use rcalc::format::NumberFormat;
+use std::io::{self, BufRead, Write};
+
+pub struct Options<'a> {
+    pub output: Output,
+    pub on_error: OnError,
+    pub marker: &'a str,
+    pub format: NumberFormat,
+    /// Adds the syntax tree to JSON results
+    pub with_ast: bool,
+    pub color: bool,
+}
+
+/// Evaluates each line of `input` on its own and writes one result per line
+/// to `out`. Blank lines print nothing, nor do lines without a value, such as
+/// function definitions, in text output.
+///
+/// Errors are reported on `err`. JSON output writes one object per line, and
+/// an object with the error takes the place of the error marker.
+///
+/// Returns the failure of the line that aborted the run, if any.
+pub fn run<F>(
+    input: impl BufRead,
+    out: &mut impl Write,
+    err: &mut impl Write,
+    options: &Options,
+    mut evaluate: F,
+) -> io::Result<Option<Failure>>
+where
+    F: FnMut(&str) -> Evaluation,
+{
+    for (i, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let evaluation = evaluate(&line);
+        if let Err(failure) = &evaluation.result {
+            for diagnostic in &failure.diagnostics {
+                write!(err, "{}", diagnostic.render_at(&line, i + 1, options.color))?;
+            }
+        }
+        match (&evaluation.result, options.output) {
+            (Err(_), _) if options.on_error == OnError::Skip => {}
+            (_, Output::Json) => {
+                let mut object =
+                    evaluation.to_json(&line, i + 1, &options.format, options.with_ast);
+                object["line"] = (i + 1).into();
+                writeln!(out, "{object}")?;
+            }
+            (Ok(Some(value)), Output::Text) => writeln!(out, "{}", options.format.format(*value))?,
+            (Ok(None), Output::Text) => {}
+            (Err(_), Output::Text) => {
+                if options.on_error == OnError::Marker {
+                    writeln!(out, "{}", options.marker)?;
+                }
+            }
+        }
+        if options.on_error == OnError::Abort {
+            if let Err(failure) = evaluation.result {
+                return Ok(Some(failure));
+            }
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Cli;
+    use clap::Parser;
+
+    /// Runs the batch over `input` with the interpreter and returns what is
+    /// written to the result and error streams.
+    fn batch(input: &str, output: Output, on_error: OnError) -> (bool, String, String) {
+        let options = Options {
+            output,
+            on_error,
+            marker: "error",
+            format: NumberFormat::default(),
+            with_ast: false,
+            color: false,
+        };
+        let cli = Cli::parse_from(["rcalc", "--lines"]);
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let mut prepared = crate::prepare(&cli, &[]);
+        let aborted = run(input.as_bytes(), &mut out, &mut err, &options, |line| {
+            crate::evaluate(&cli, &mut prepared, line)
+        })
+        .unwrap();
+        let string = |bytes| String::from_utf8(bytes).unwrap();
+        (aborted.is_none(), string(out), string(err))
+    }
+
+    #[test]
+    fn lines() {
+        let (completed, out, err) =
+            batch("1 + 2\n\nPI * 2\nf(x) = x\n", Output::Text, OnError::Abort);
+        assert!(completed);
+        assert_eq!(out, format!("3\n{}\n", std::f64::consts::PI * 2.0));
+        assert_eq!(err, "");
+
+        // Lines do not see each other's definitions
+        let (completed, out, _) = batch("let x = 1\nx\nlet x = 2", Output::Text, OnError::Skip);
+        assert!(completed);
+        assert_eq!(out, "1\n2\n");
+    }
+
+    #[test]
+    fn error_policy() {
+        let input = "1\n2 +\n3\nsqr(4)\n5\n";
+        let (completed, out, err) = batch(input, Output::Text, OnError::Abort);
+        assert!(!completed);
+        assert_eq!(out, "1\n");
+        assert!(err.starts_with("error: unexpected end of input\n --> 2:4\n"));
+
+        let (completed, out, err) = batch(input, Output::Text, OnError::Skip);
+        assert!(completed);
+        assert_eq!(out, "1\n3\n5\n");
+        assert!(err.contains(" --> 4:1\n"));
+        assert!(err.contains("did you mean `sqrt`?"));
+
+        let (completed, out, _) = batch(input, Output::Text, OnError::Marker);
+        assert!(completed);
+        assert_eq!(out, "1\nerror\n3\nerror\n5\n");
+    }
+
+    #[test]
+    fn json_lines() {
+        let input = "1\n2 +\n\nsqr(4)\n";
+        let parse = |out: &str| -> Vec<serde_json::Value> {
+            out.lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        };
+
+        let (completed, out, err) = batch(input, Output::Json, OnError::Marker);
+        assert!(completed);
+        assert!(err.contains(" --> 4:1\n"));
+        let objects = parse(&out);
+        assert_eq!(objects.len(), 3);
+        assert_eq!(objects[0]["result"], 1.0);
+        assert_eq!(objects[0]["line"], 1);
+        assert_eq!(objects[1]["error"]["kind"], "parse");
+        assert_eq!(objects[1]["error"]["span"]["line"], 2);
+        assert_eq!(objects[2]["line"], 4);
+
+        let (_, out, _) = batch(input, Output::Json, OnError::Skip);
+        assert_eq!(parse(&out).len(), 1);
+
+        let (completed, out, _) = batch(input, Output::Json, OnError::Abort);
+        assert!(!completed);
+        let objects = parse(&out);
+        assert_eq!(objects.len(), 2);
+        assert_eq!(objects[1]["success"], false);
+    }
+}
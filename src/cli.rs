@@ -1,11 +1,45 @@
-use clap::Parser;
-use std::collections::HashMap;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use rcalc::format::{Grouping, NonFinite, Notation, NumberFormat};
+use rcalc::parser::{calc_parser, KEYWORDS};
+use rcalc::visitor::Calculator;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+
+const EXIT_STATUS: &str = "\
+Exit status:
+  0  success
+  1  the program does not parse
+  2  the program fails to evaluate
+  3  the backend is not available, such as `--jit` without feature `jit`
+  4  invalid usage, such as an unknown option or an unreadable file";
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, after_help = EXIT_STATUS)]
 #[command(allow_negative_numbers = true)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Cli {
-    pub expr: String,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Expression to evaluate, `-` reads the program from standard input. An
+    /// interactive session starts without one
+    pub expr: Option<String>,
+
+    /// Read the program from a file, `-` for standard input
+    #[arg(short, long, value_name = "FILE", conflicts_with = "expr")]
+    pub file: Option<PathBuf>,
+
+    /// Evaluate each line of the input on its own and print one result per line
+    #[arg(long)]
+    pub lines: bool,
+
+    /// What to do when a line fails in `--lines` mode
+    #[arg(long, value_enum, default_value_t = OnError::Abort, requires = "lines")]
+    pub on_error: OnError,
+
+    /// Printed in place of the result of a failed line with `--on-error marker`
+    #[arg(long, value_name = "TEXT", default_value = "error", requires = "lines")]
+    pub error_marker: String,
 
     #[arg(short, long)]
     pub jit: bool,
@@ -13,76 +47,446 @@ pub struct Cli {
     #[arg(short, long)]
     pub verbose: bool,
 
+    /// Number of decimals to print
     #[arg(short, long)]
     pub precision: Option<usize>,
 
+    /// Number of significant digits to print
+    #[arg(long, value_name = "N", conflicts_with = "precision")]
+    #[arg(value_parser = clap::value_parser!(u32).range(1..))]
+    pub sig_digits: Option<u32>,
+
+    /// Notation of results
+    #[arg(long, value_name = "NOTATION", value_enum, default_value_t = NotationArg::Auto)]
+    pub format: NotationArg,
+
+    /// Separator between groups of three digits before the point
+    #[arg(long, value_name = "SEPARATOR", value_enum, default_value_t = GroupingArg::None)]
+    pub group: GroupingArg,
+
+    /// How to spell NaN and infinity
+    #[arg(long, value_name = "STYLE", value_enum, default_value_t = NonFiniteArg::Rust)]
+    pub non_finite: NonFiniteArg,
+
     #[arg(long)]
     pub pure: bool,
 
-    #[arg(short)]
-    pub a: Option<f64>,
+    /// Treat division by zero as an error, only the interpreter checks for it
+    #[arg(long, conflicts_with = "jit")]
+    pub strict: bool,
+
+    /// How to print results, `json` prints an object per program, or per line
+    /// with `--lines`, and leaves out the listings of `--emit-llvm` and `--emit-asm`
+    #[arg(long, value_enum, default_value_t = Output::Text)]
+    pub output: Output,
+
+    /// When to color error messages
+    #[arg(long, value_enum, default_value_t = Color::Auto)]
+    pub color: Color,
+
+    /// Optimization level of the JIT backend
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=3), requires = "jit")]
+    pub opt_level: u8,
+
+    /// Print the LLVM IR of the JIT compiled program
+    #[arg(long, requires = "jit")]
+    pub emit_llvm: bool,
 
-    #[arg(short)]
-    pub b: Option<f64>,
+    /// Print the native assembly of the JIT compiled program
+    #[arg(long, requires = "jit")]
+    pub emit_asm: bool,
 
-    #[arg(short)]
-    pub c: Option<f64>,
+    /// Define a variable, can be repeated
+    #[arg(short = 'D', long = "var", value_name = "NAME=VALUE", value_parser = parse_var)]
+    pub vars: Vec<(String, f64)>,
 
-    #[arg(short)]
-    pub d: Option<f64>,
+    /// Load variables from a `.toml`, `.json` or `.env` file, `--var` takes precedence
+    #[arg(long, value_name = "FILE")]
+    pub vars_file: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Compile a formula into an object file or a shared library, with a C header
+    Compile(CompileArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct CompileArgs {
+    pub expr: String,
 
-    #[arg(short)]
-    pub e: Option<f64>,
+    /// Parameters of the generated function, in order
+    #[arg(long, value_delimiter = ',')]
+    pub params: Vec<String>,
 
-    #[arg(short)]
-    pub x: Option<f64>,
+    /// Name of the exported function
+    #[arg(long)]
+    pub name: String,
 
-    #[arg(short)]
-    pub y: Option<f64>,
+    /// A `.o` file gives an object file, anything else a shared library
+    #[arg(short, long)]
+    pub output: PathBuf,
 
-    #[arg(short)]
-    pub z: Option<f64>,
+    /// Where to write the C header, next to the output by default
+    #[arg(long)]
+    pub header: Option<PathBuf>,
+
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u8).range(0..=3))]
+    pub opt_level: u8,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Color {
+    Auto,
+    Always,
+    Never,
 }
 
-pub fn get_variables(cli: &Cli) -> HashMap<String, f64> {
-    let mut variables = HashMap::new();
-    if let Some(a) = cli.a {
-        variables.insert("a".into(), a);
+impl Color {
+    /// Resolves `auto` against the given stream and the `NO_COLOR` convention.
+    pub fn enabled(self, stream: &impl IsTerminal) -> bool {
+        match self {
+            Color::Always => true,
+            Color::Never => false,
+            Color::Auto => stream.is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+        }
     }
-    if let Some(b) = cli.b {
-        variables.insert("b".into(), b);
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Output {
+    Text,
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnError {
+    /// Stop at the first failed line
+    Abort,
+    /// Leave the failed line out of the results
+    Skip,
+    /// Print the error marker in place of the result
+    Marker,
+}
+
+/// The values of `--format`, one for each [`Notation`]. The formatter of
+/// the library knows nothing of the command line.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NotationArg {
+    /// The shortest form, or like `%g` when significant digits are given:
+    /// fixed for moderate magnitudes and scientific otherwise
+    Auto,
+    /// Never an exponent
+    Fixed,
+    /// One digit before the point, `1.5e-3`
+    Sci,
+    /// Exponents are multiples of three, `0.015` is `15e-3`
+    Eng,
+}
+
+impl From<NotationArg> for Notation {
+    fn from(arg: NotationArg) -> Self {
+        match arg {
+            NotationArg::Auto => Notation::Auto,
+            NotationArg::Fixed => Notation::Fixed,
+            NotationArg::Sci => Notation::Sci,
+            NotationArg::Eng => Notation::Eng,
+        }
     }
-    if let Some(c) = cli.c {
-        variables.insert("c".into(), c);
+}
+
+impl From<Notation> for NotationArg {
+    fn from(notation: Notation) -> Self {
+        match notation {
+            Notation::Auto => NotationArg::Auto,
+            Notation::Fixed => NotationArg::Fixed,
+            Notation::Sci => NotationArg::Sci,
+            Notation::Eng => NotationArg::Eng,
+        }
     }
-    if let Some(d) = cli.d {
-        variables.insert("d".into(), d);
+}
+
+/// The values of `--group`, one for each [`Grouping`].
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GroupingArg {
+    None,
+    /// `1_234_567`, as in Rust literals
+    Underscore,
+    /// `1,234,567`
+    Comma,
+    /// `1 234 567`
+    Space,
+}
+
+impl From<GroupingArg> for Grouping {
+    fn from(arg: GroupingArg) -> Self {
+        match arg {
+            GroupingArg::None => Grouping::None,
+            GroupingArg::Underscore => Grouping::Underscore,
+            GroupingArg::Comma => Grouping::Comma,
+            GroupingArg::Space => Grouping::Space,
+        }
+    }
+}
+
+/// The values of `--non-finite`, one for each [`NonFinite`].
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NonFiniteArg {
+    /// `NaN`, `inf` and `-inf`
+    Rust,
+    /// `nan`, `inf` and `-inf`
+    C,
+    /// `NaN`, `Infinity` and `-Infinity`
+    Js,
+}
+
+impl From<NonFiniteArg> for NonFinite {
+    fn from(arg: NonFiniteArg) -> Self {
+        match arg {
+            NonFiniteArg::Rust => NonFinite::Rust,
+            NonFiniteArg::C => NonFinite::C,
+            NonFiniteArg::Js => NonFinite::Js,
+        }
     }
-    if let Some(e) = cli.e {
-        variables.insert("e".into(), e);
+}
+
+impl Cli {
+    /// How results are printed, in text as well as in JSON output.
+    pub fn number_format(&self) -> NumberFormat {
+        NumberFormat {
+            notation: self.format.into(),
+            precision: self.precision,
+            sig_digits: self.sig_digits.map(|digits| digits as usize),
+            grouping: self.group.into(),
+            non_finite: self.non_finite.into(),
+        }
+    }
+}
+
+/// Where the program comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    Expr(String),
+    Stdin,
+    File(PathBuf),
+}
+
+impl Input {
+    /// The input given on the command line, `--lines` alone reads standard
+    /// input. `None` asks for an interactive session.
+    pub fn from_cli(cli: &Cli) -> Option<Self> {
+        match (&cli.expr, &cli.file) {
+            (Some(expr), _) if expr == "-" => Some(Input::Stdin),
+            (Some(expr), _) => Some(Input::Expr(expr.clone())),
+            (None, Some(path)) if path.as_os_str() == "-" => Some(Input::Stdin),
+            (None, Some(path)) => Some(Input::File(path.clone())),
+            (None, None) if cli.lines => Some(Input::Stdin),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Variables of `--vars-file` then `--var`, in order. A later definition of
+/// a name replaces the earlier one.
+pub fn get_variables(cli: &Cli) -> Result<Vec<(String, f64)>, String> {
+    let mut variables: Vec<(String, f64)> = Vec::new();
+    let from_file = match &cli.vars_file {
+        Some(path) => load_vars_file(path)?,
+        None => Vec::new(),
+    };
+    for (name, value) in from_file.into_iter().chain(cli.vars.iter().cloned()) {
+        match variables.iter_mut().find(|(defined, _)| *defined == name) {
+            Some(variable) => variable.1 = value,
+            None => variables.push((name, value)),
+        }
+    }
+    Ok(variables)
+}
+
+fn parse_var(s: &str) -> Result<(String, f64), String> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=VALUE, found `{s}`"))?;
+    let name = name.trim();
+    check_name(name)?;
+    Ok((name.to_string(), parse_value(name, value.trim())?))
+}
+
+fn parse_value(name: &str, value: &str) -> Result<f64, String> {
+    value
+        .parse()
+        .map_err(|_| format!("value of `{name}` is not a number: `{value}`"))
+}
+
+/// Checks that `name` can be referred to from an expression, and does not
+/// take the place of a keyword or a preset constant.
+fn check_name(name: &str) -> Result<(), String> {
+    if calc_parser::identifier(name).is_err() {
+        return Err(format!("`{name}` is not a valid variable name"));
     }
-    if let Some(x) = cli.x {
-        variables.insert("x".into(), x);
+    if KEYWORDS.contains(&name) {
+        return Err(format!("`{name}` is a keyword"));
     }
-    if let Some(y) = cli.y {
-        variables.insert("y".into(), y);
+    if Calculator::preset_variables().any(|preset| preset == name) {
+        return Err(format!("`{name}` is a preset constant"));
     }
-    if let Some(z) = cli.z {
-        variables.insert("z".into(), z);
+    Ok(())
+}
+
+/// Reads variables from a file, its format is told by its extension:
+///
+/// - `.toml`: top-level keys, `rate = 0.05`
+/// - `.json`: an object, `{"rate": 0.05}`
+/// - `.env`: `rate=0.05` lines, blank lines and `#` comments are skipped
+fn load_vars_file(path: &Path) -> Result<Vec<(String, f64)>, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("failed to read `{}`: {e}", path.display()))?;
+    let format = match path.file_name().and_then(|name| name.to_str()) {
+        Some(".env") => "env",
+        _ => path.extension().and_then(|ext| ext.to_str()).unwrap_or(""),
+    };
+    let variables = match format {
+        "toml" => parse_toml_vars(&content),
+        "json" => parse_json_vars(&content),
+        "env" => parse_env_vars(&content),
+        _ => Err("unknown format, expected a .toml, .json or .env file".into()),
+    };
+    let variables = variables.map_err(|e| format!("{}: {e}", path.display()))?;
+    for (name, _) in &variables {
+        check_name(name).map_err(|e| format!("{}: {e}", path.display()))?;
     }
-    variables
+    Ok(variables)
+}
+
+fn parse_toml_vars(content: &str) -> Result<Vec<(String, f64)>, String> {
+    let table: toml::Table = content
+        .parse()
+        .map_err(|e: toml::de::Error| e.to_string())?;
+    table
+        .into_iter()
+        .map(|(name, value)| match value {
+            toml::Value::Float(value) => Ok((name, value)),
+            toml::Value::Integer(value) => Ok((name, value as f64)),
+            _ => Err(format!("value of `{name}` is not a number: `{value}`")),
+        })
+        .collect()
+}
+
+fn parse_json_vars(content: &str) -> Result<Vec<(String, f64)>, String> {
+    let value: serde_json::Value = serde_json::from_str(content).map_err(|e| e.to_string())?;
+    let serde_json::Value::Object(object) = value else {
+        return Err("expected an object of numbers".into());
+    };
+    object
+        .into_iter()
+        .map(|(name, value)| match value.as_f64() {
+            Some(number) => Ok((name, number)),
+            None => Err(format!("value of `{name}` is not a number: `{value}`")),
+        })
+        .collect()
+}
+
+fn parse_env_vars(content: &str) -> Result<Vec<(String, f64)>, String> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| {
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("line {number}: expected NAME=VALUE"))?;
+            let name = name.trim().to_string();
+            let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+            let value = parse_value(&name, value).map_err(|e| format!("line {number}: {e}"))?;
+            Ok((name, value))
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::utils::assert_close;
 
     #[test]
     fn common() {
         let expr = "a * b + 9";
         let cli = Cli::parse_from(["rcalc", expr]);
-        assert_eq!(expr, cli.expr);
+        assert_eq!(Some(expr), cli.expr.as_deref());
+        assert!(cli.command.is_none());
+
+        assert_eq!(Cli::parse_from(["rcalc"]).expr, None);
+    }
+
+    #[test]
+    fn input() {
+        let input = |args: &[&str]| Input::from_cli(&Cli::parse_from(args));
+        assert_eq!(input(&["rcalc", "1+2"]), Some(Input::Expr("1+2".into())));
+        assert_eq!(input(&["rcalc", "-"]), Some(Input::Stdin));
+        assert_eq!(input(&["rcalc", "-f", "-"]), Some(Input::Stdin));
+        assert_eq!(
+            input(&["rcalc", "--file", "loan.calc"]),
+            Some(Input::File("loan.calc".into()))
+        );
+        assert_eq!(input(&["rcalc", "--lines"]), Some(Input::Stdin));
+        assert_eq!(input(&["rcalc"]), None);
+        assert!(Cli::try_parse_from(["rcalc", "1+2", "-f", "loan.calc"]).is_err());
+    }
+
+    #[test]
+    fn lines() {
+        let cli = Cli::parse_from(["rcalc", "-"]);
+        assert!(!cli.lines);
+        assert_eq!(cli.on_error, OnError::Abort);
+        assert_eq!(cli.error_marker, "error");
+
+        let cli = Cli::parse_from(["rcalc", "-", "--lines", "--on-error", "skip"]);
+        assert!(cli.lines);
+        assert_eq!(cli.on_error, OnError::Skip);
+
+        let cli = Cli::parse_from([
+            "rcalc",
+            "--lines",
+            "--on-error=marker",
+            "--error-marker",
+            "NaN",
+        ]);
+        assert_eq!(cli.on_error, OnError::Marker);
+        assert_eq!(cli.error_marker, "NaN");
+
+        assert!(Cli::try_parse_from(["rcalc", "-", "--on-error", "skip"]).is_err());
+        assert!(Cli::try_parse_from(["rcalc", "-", "--lines", "--on-error", "retry"]).is_err());
+    }
+
+    #[test]
+    fn compile() {
+        let cli = Cli::parse_from([
+            "rcalc",
+            "compile",
+            "a*x+b",
+            "--params",
+            "a,x,b",
+            "--name",
+            "linear",
+            "-o",
+            "liblinear.so",
+        ]);
+        assert_eq!(cli.expr, None);
+        let Some(Command::Compile(args)) = cli.command else {
+            panic!("Expected the compile command");
+        };
+        assert_eq!(args.expr, "a*x+b");
+        assert_eq!(args.params, ["a", "x", "b"]);
+        assert_eq!(args.name, "linear");
+        assert_eq!(args.output, PathBuf::from("liblinear.so"));
+        assert_eq!(args.header, None);
+        assert_eq!(args.opt_level, 2);
+
+        assert!(Cli::try_parse_from(["rcalc", "compile", "x", "-o", "x.o"]).is_err());
+        assert!(Cli::try_parse_from([
+            "rcalc", "-D", "a=1", "compile", "x", "--name", "f", "-o", "f.o"
+        ])
+        .is_err());
     }
 
     #[test]
@@ -140,20 +544,166 @@ mod tests {
         assert!(cli.pure);
     }
 
+    #[test]
+    fn strict() {
+        let expr = "expr";
+        let cli = Cli::parse_from(["rcalc", expr]);
+        assert!(!cli.strict);
+
+        let cli = Cli::parse_from(["rcalc", expr, "--strict"]);
+        assert!(cli.strict);
+
+        assert!(Cli::try_parse_from(["rcalc", expr, "--strict", "--jit"]).is_err());
+    }
+
+    #[test]
+    fn jit_output() {
+        let expr = "expr";
+        let cli = Cli::parse_from(["rcalc", expr]);
+        assert_eq!(cli.opt_level, 0);
+        assert!(!cli.emit_llvm);
+        assert!(!cli.emit_asm);
+
+        let cli = Cli::parse_from(["rcalc", expr, "-j", "--opt-level", "3", "--emit-llvm"]);
+        assert_eq!(cli.opt_level, 3);
+        assert!(cli.emit_llvm);
+
+        let cli = Cli::parse_from(["rcalc", expr, "-j", "--opt-level=2", "--emit-asm"]);
+        assert_eq!(cli.opt_level, 2);
+        assert!(cli.emit_asm);
+
+        assert!(Cli::try_parse_from(["rcalc", expr, "-j", "--opt-level", "4"]).is_err());
+        assert!(Cli::try_parse_from(["rcalc", expr, "--emit-llvm"]).is_err());
+    }
+
+    #[test]
+    fn output() {
+        let expr = "expr";
+        let cli = Cli::parse_from(["rcalc", expr]);
+        assert_eq!(cli.output, Output::Text);
+
+        let cli = Cli::parse_from(["rcalc", expr, "--output", "json"]);
+        assert_eq!(cli.output, Output::Json);
+
+        assert!(Cli::try_parse_from(["rcalc", expr, "--output=yaml"]).is_err());
+    }
+
+    #[test]
+    fn number_format() {
+        let expr = "expr";
+        let cli = Cli::parse_from(["rcalc", expr]);
+        assert_eq!(cli.number_format(), NumberFormat::default());
+
+        let cli = Cli::parse_from(["rcalc", expr, "-p", "2", "--format", "fixed"]);
+        assert_eq!(cli.number_format().notation, Notation::Fixed);
+        assert_eq!(cli.number_format().precision, Some(2));
+
+        let args = [
+            "rcalc",
+            expr,
+            "--format=eng",
+            "--sig-digits",
+            "4",
+            "--group",
+            "comma",
+        ];
+        let format = Cli::parse_from(args).number_format();
+        assert_eq!(format.notation, Notation::Eng);
+        assert_eq!(format.sig_digits, Some(4));
+        assert_eq!(format.grouping, Grouping::Comma);
+
+        let cli = Cli::parse_from(["rcalc", expr, "--non-finite", "js"]);
+        assert_eq!(cli.number_format().non_finite, NonFinite::Js);
+
+        assert!(Cli::try_parse_from(["rcalc", expr, "--format", "exp"]).is_err());
+        assert!(Cli::try_parse_from(["rcalc", expr, "--sig-digits", "0"]).is_err());
+        assert!(Cli::try_parse_from(["rcalc", expr, "-p", "2", "--sig-digits", "2"]).is_err());
+        assert!(Cli::try_parse_from(["rcalc", expr, "--group", "dot"]).is_err());
+
+        // The REPL shows the notation under its command line name
+        for &notation in NotationArg::value_variants() {
+            assert_eq!(NotationArg::from(Notation::from(notation)), notation);
+        }
+    }
+
+    #[test]
+    fn color() {
+        let expr = "expr";
+        let cli = Cli::parse_from(["rcalc", expr]);
+        assert_eq!(cli.color, Color::Auto);
+
+        let cli = Cli::parse_from(["rcalc", expr, "--color", "never"]);
+        assert_eq!(cli.color, Color::Never);
+        assert!(!cli.color.enabled(&std::io::stderr()));
+
+        let cli = Cli::parse_from(["rcalc", expr, "--color=always"]);
+        assert!(cli.color.enabled(&std::io::stderr()));
+    }
+
     #[test]
     fn variables() {
         let expr = "expr";
         let cli = Cli::parse_from(["rcalc", expr]);
-        let variables = get_variables(&cli);
-        assert!(variables.is_empty());
+        assert_eq!(get_variables(&cli), Ok(vec![]));
+
+        let cli = Cli::parse_from(["rcalc", expr, "-D", "rate=-1.1", "--var=b=-0.999"]);
+        let variables = get_variables(&cli).unwrap();
+        assert_eq!(variables, [("rate".into(), -1.1), ("b".into(), -0.999)]);
 
-        let cli = Cli::parse_from(["rcalc", expr, "-a", "-1.1"]);
-        let variables = get_variables(&cli);
-        assert_eq!(variables.get("b"), None);
-        assert_close(variables["a"], -1.1);
+        // The last definition wins
+        let cli = Cli::parse_from(["rcalc", expr, "-D", "x=1", "-D", "x = 2"]);
+        assert_eq!(get_variables(&cli), Ok(vec![("x".into(), 2.0)]));
 
-        let cli = Cli::parse_from(["rcalc", expr, "-b=-0.999"]);
-        let variables = get_variables(&cli);
-        assert_close(variables["b"], -0.999);
+        for invalid in ["x", "1x=1", "x y=1", "x=one", "PI=3", "E=2"] {
+            assert!(Cli::try_parse_from(["rcalc", expr, "-D", invalid]).is_err());
+        }
+    }
+
+    #[test]
+    fn vars_file() {
+        let dir = std::env::temp_dir().join(format!("rcalc-vars-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let load = |file: &str, content: &str| {
+            let path = dir.join(file);
+            std::fs::write(&path, content).unwrap();
+            let path = path.to_str().unwrap().to_string();
+            let cli = Cli::parse_from(["rcalc", "expr", "--vars-file", &path, "-D", "b=10"]);
+            // Table formats do not keep the order of the file
+            get_variables(&cli).map(|mut variables| {
+                variables.sort_by(|a, b| a.0.cmp(&b.0));
+                variables
+            })
+        };
+        let expected = vec![
+            ("b".into(), 10.0),
+            ("principal".into(), 1000.0),
+            ("rate".into(), 0.05),
+        ];
+
+        assert_eq!(
+            load("vars.toml", "principal = 1000\nrate = 0.05\nb = 1"),
+            Ok(expected.clone())
+        );
+        assert_eq!(
+            load("vars.json", r#"{"principal": 1000, "rate": 0.05, "b": 1}"#),
+            Ok(expected.clone())
+        );
+        let env = "# loan\nprincipal=1000\n\nrate = \"0.05\"\nb=1\n";
+        assert_eq!(load("vars.env", env), Ok(expected.clone()));
+        assert_eq!(load(".env", env), Ok(expected));
+
+        assert!(load("vars.toml", "rate = \"high\"").is_err());
+        assert!(load("vars.json", "[1, 2]").is_err());
+        assert!(load("vars.env", "rate").unwrap_err().contains("line 1"));
+        assert!(load("vars.env", "TAU=6")
+            .unwrap_err()
+            .contains("preset constant"));
+        assert!(load("vars.yaml", "rate: 1").is_err());
+        let missing = dir.join("missing.toml");
+        let cli = Cli::parse_from(["rcalc", "x", "--vars-file", missing.to_str().unwrap()]);
+        assert!(get_variables(&cli)
+            .unwrap_err()
+            .starts_with("failed to read"));
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
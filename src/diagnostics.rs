@@ -0,0 +1,464 @@
+use crate::ast::Span;
+use crate::visitor::EvalError;
+use peg::error::ParseError;
+use peg::str::LineCol;
+use std::fmt::Write;
+#[cfg(feature = "cli")]
+use unicode_width::UnicodeWidthChar;
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const CYAN: &str = "\x1b[1;36m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// An error message pointing at a region of the source, rendered as a
+/// labelled snippet:
+///
+/// ```text
+/// error: unexpected end of input
+///  --> 1:4
+///   |
+/// 1 | 1 +
+///   |    ^ expected a number, an identifier, `(`, `+` or `-`
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Option<Span>,
+    pub label: Option<String>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            span: None,
+            label: None,
+            help: None,
+        }
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn from_parse_error(input: &str, error: &ParseError<LineCol>) -> Self {
+        let offset = error.location.offset;
+        let found = input[offset..].chars().next();
+        let message = match found {
+            None => "unexpected end of input".to_string(),
+            Some('\n') | Some('\r') => "unexpected end of line".to_string(),
+            Some(c) => format!("unexpected `{c}`"),
+        };
+        let span = Span::new(offset, offset + found.map_or(0, char::len_utf8));
+        // A rule rejected what it matched, its reason beats the list of
+        // tokens that could have followed
+        if let Some(reason) = error.expected.tokens().find(|token| is_reason(token)) {
+            return Self::new(format!("expected {reason}")).with_span(span);
+        }
+        let diagnostic = Self::new(message).with_span(span);
+        match describe_expected(error.expected.tokens()) {
+            Some(expected) => diagnostic.with_label(format!("expected {expected}")),
+            None => diagnostic,
+        }
+    }
+
+    pub fn from_eval_error(error: &EvalError) -> Self {
+        let diagnostic = Self::new(error.to_string());
+        match error.span() {
+            Some(span) => diagnostic.with_span(span),
+            None => diagnostic,
+        }
+    }
+
+    /// Renders the diagnostic against the source it was produced from,
+    /// using ANSI colors when `color` is set.
+    pub fn render(&self, source: &str, color: bool) -> String {
+        self.render_at(source, 1, color)
+    }
+
+    /// Like [`Diagnostic::render`], for a source that starts at line
+    /// `first_line` of a larger input.
+    pub fn render_at(&self, source: &str, first_line: usize, color: bool) -> String {
+        let paint = |style: &str, text: &str| {
+            if color {
+                format!("{style}{text}{RESET}")
+            } else {
+                text.to_string()
+            }
+        };
+
+        let mut out = String::new();
+        writeln!(
+            out,
+            "{}{}",
+            paint(RED, "error"),
+            paint(BOLD, &format!(": {}", self.message))
+        )
+        .unwrap();
+
+        let mut gutter = String::new();
+        if let Some(span) = self.span {
+            let start = span.start.min(source.len());
+            let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+            let line_end = source[start..]
+                .find('\n')
+                .map_or(source.len(), |i| start + i);
+            let line = source[line_start..line_end].trim_end_matches('\r');
+            let (line_no, column) = location(source, start);
+            let line_no = line_no + first_line - 1;
+
+            // Only the first line of a multi-line span is underlined
+            let end = span.end.clamp(start, line_start + line.len());
+            let padding: String = source[line_start..start]
+                .chars()
+                .map(|c| {
+                    if c == '\t' {
+                        "\t".to_string()
+                    } else {
+                        " ".repeat(width(c))
+                    }
+                })
+                .collect();
+            let carets = source[start..end].chars().map(width).sum::<usize>().max(1);
+
+            let number = line_no.to_string();
+            gutter = " ".repeat(number.len());
+            writeln!(out, "{gutter}{} {line_no}:{column}", paint(BLUE, "-->")).unwrap();
+            writeln!(out, "{gutter} {}", paint(BLUE, "|")).unwrap();
+            writeln!(out, "{} {line}", paint(BLUE, &format!("{number} |"))).unwrap();
+            write!(
+                out,
+                "{gutter} {} {padding}{}",
+                paint(BLUE, "|"),
+                paint(RED, &"^".repeat(carets))
+            )
+            .unwrap();
+            if let Some(label) = &self.label {
+                write!(out, " {}", paint(RED, label)).unwrap();
+            }
+            out.push('\n');
+        } else if let Some(label) = &self.label {
+            writeln!(out, " {} {label}", paint(BLUE, "=")).unwrap();
+        }
+
+        if let Some(help) = &self.help {
+            writeln!(
+                out,
+                "{gutter} {} {}: {help}",
+                paint(BLUE, "="),
+                paint(CYAN, "help")
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+/// The 1-based line and column, counted in characters, of the byte `offset`
+/// of `source`.
+pub fn location(source: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(source.len());
+    let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line = source[..offset].matches('\n').count() + 1;
+    (line, source[line_start..offset].chars().count() + 1)
+}
+
+#[cfg(feature = "cli")]
+fn width(c: char) -> usize {
+    c.width().unwrap_or(0)
+}
+
+/// Without the width tables of the `cli` feature, every character is taken
+/// to be one column wide.
+#[cfg(not(feature = "cli"))]
+fn width(_c: char) -> usize {
+    1
+}
+
+/// A token that a parse error expected.
+#[derive(Debug, PartialEq)]
+enum Expected {
+    /// A class of tokens, such as `number`
+    Kind(String),
+    /// Text to type as is, such as `(`
+    Literal(String),
+}
+
+/// Names the tokens peg reports in words, such as `number`, `identifier`
+/// or `(`. Classes of tokens come ahead of the literal ones, and the
+/// messages of rules rejecting what they matched are left out.
+pub fn expected_tokens<'a>(tokens: impl Iterator<Item = &'a str>) -> Vec<String> {
+    expected(tokens)
+        .into_iter()
+        .map(|expected| match expected {
+            Expected::Kind(name) | Expected::Literal(name) => name,
+        })
+        .collect()
+}
+
+fn expected<'a>(tokens: impl Iterator<Item = &'a str>) -> Vec<Expected> {
+    let mut expected: Vec<Expected> = Vec::new();
+    for token in tokens.filter(|token| !is_reason(token)) {
+        if let Some(token) = expected_token(token) {
+            if !expected.contains(&token) {
+                expected.push(token);
+            }
+        }
+    }
+    // Whole-word descriptions read better ahead of punctuation
+    expected.sort_by_key(|token| matches!(token, Expected::Literal(_)));
+    expected
+}
+
+/// Turns the raw tokens peg reports into a sentence such as
+/// "a number, an identifier or `(`".
+fn describe_expected<'a>(tokens: impl Iterator<Item = &'a str>) -> Option<String> {
+    let described: Vec<String> = expected(tokens)
+        .into_iter()
+        .map(|expected| match expected {
+            Expected::Kind(name) => match name.as_str() {
+                "number" | "newline" => format!("a {name}"),
+                "identifier" => format!("an {name}"),
+                _ => name,
+            },
+            Expected::Literal(literal) => format!("`{literal}`"),
+        })
+        .collect();
+    let (last, rest) = described.split_last()?;
+    if rest.is_empty() {
+        Some(last.clone())
+    } else {
+        Some(format!("{} or {last}", rest.join(", ")))
+    }
+}
+
+fn expected_token(token: &str) -> Option<Expected> {
+    if token == "EOF" {
+        return Some(Expected::Kind("end of input".into()));
+    }
+    if let Some(literal) = token.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+        return match literal {
+            // Whitespace is accepted almost everywhere, listing it is noise
+            " " | "\\t" | "\\r" => None,
+            "\\n" => Some(Expected::Kind("newline".into())),
+            _ if literal.bytes().all(|b| b.is_ascii_digit()) => {
+                Some(Expected::Kind("number".into()))
+            }
+            _ => Some(Expected::Literal(literal.into())),
+        };
+    }
+    if token.contains("'a'..='z'") {
+        Some(Expected::Kind("identifier".into()))
+    } else if token.contains("'9'") {
+        Some(Expected::Kind("number".into()))
+    } else {
+        Some(Expected::Kind(token.into()))
+    }
+}
+
+/// Messages from `{? }` actions in the grammar, as opposed to tokens.
+fn is_reason(token: &str) -> bool {
+    token != "EOF" && !token.starts_with('"') && !token.starts_with('[')
+}
+
+/// Picks the candidate closest to `name`, if any is close enough to be a
+/// plausible typo. A candidate must keep some of the letters of `name`, so
+/// a short name like `x` is only matched by one that differs in case.
+pub fn did_you_mean<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let length = name.chars().count();
+    let threshold = (length / 3).max(1).min(length.saturating_sub(1));
+    let lowercase = name.to_lowercase();
+    candidates
+        .filter(|candidate| *candidate != name)
+        .map(|candidate| {
+            let distance = levenshtein(&lowercase, &candidate.to_lowercase());
+            (distance, candidate)
+        })
+        .filter(|(distance, _)| *distance <= threshold)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// Number of single character insertions, deletions and substitutions that
+/// turn `a` into `b`.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::calc_parser;
+    use crate::visitor::Calculator;
+
+    fn parse_diagnostic(input: &str) -> Diagnostic {
+        let e = calc_parser::program(input).unwrap_err();
+        Diagnostic::from_parse_error(input, &e)
+    }
+
+    #[test]
+    fn end_of_input() {
+        let input = "1 +";
+        let diagnostic = parse_diagnostic(input);
+        assert_eq!(diagnostic.message, "unexpected end of input");
+        let label = diagnostic.label.clone().unwrap();
+        assert!(label.starts_with("expected a number, an identifier"));
+        assert!(label.contains("`(`"));
+        assert_eq!(
+            diagnostic.render(input, false),
+            format!("error: unexpected end of input\n --> 1:4\n  |\n1 | 1 +\n  |    ^ {label}\n")
+        );
+    }
+
+    #[test]
+    fn unexpected_char() {
+        let input = "1 + )";
+        let diagnostic = parse_diagnostic(input);
+        assert_eq!(diagnostic.message, "unexpected `)`");
+        assert!(diagnostic
+            .render(input, false)
+            .contains("1 | 1 + )\n  |     ^ expected"));
+    }
+
+    #[test]
+    fn multi_line() {
+        let input = "let x = 1\nx * 2\n\nx + * 3";
+        let rendered = parse_diagnostic(input).render(input, false);
+        assert!(rendered.contains(" --> 4:5\n"));
+        assert!(rendered.contains("4 | x + * 3\n  |     ^"));
+
+        let input = "let x = 1\nx +\n";
+        let diagnostic = parse_diagnostic(input);
+        assert_eq!(diagnostic.message, "unexpected end of line");
+        assert!(diagnostic
+            .render(input, false)
+            .contains("2 | x +\n  |    ^"));
+    }
+
+    #[test]
+    fn line_offset() {
+        let input = "2 *";
+        let rendered = parse_diagnostic(input).render_at(input, 12, false);
+        assert!(rendered.contains("  --> 12:4\n   |\n12 | 2 *\n   |    ^"));
+        assert_eq!(location("é\nab", 4), (2, 2));
+        assert_eq!(location("x", 5), (1, 2));
+    }
+
+    #[test]
+    #[cfg(feature = "cli")]
+    fn unicode_columns() {
+        let input = "1 + 日本";
+        let diagnostic = parse_diagnostic(input);
+        assert_eq!(diagnostic.message, "unexpected `日`");
+        // Wide characters take two carets
+        assert!(diagnostic
+            .render(input, false)
+            .contains("  |     ^^ expected"));
+
+        let input = "é + 日本 + x";
+        let start = input.find('x').unwrap();
+        let rendered = Diagnostic::new("unknown variable `x`")
+            .with_span(Span::new(start, start + 1))
+            .render(input, false);
+        // `x` is the 10th character but sits at display column 12
+        assert!(rendered.contains(" --> 1:10\n"));
+        assert!(rendered.ends_with(&format!("  | {}^\n", " ".repeat(11))));
+    }
+
+    #[test]
+    fn grammar_messages() {
+        let input = "f(x, x) = x";
+        let diagnostic = parse_diagnostic(input);
+        assert_eq!(diagnostic.message, "expected distinct parameter names");
+        assert_eq!(diagnostic.label, None);
+    }
+
+    #[test]
+    fn expected_names() {
+        let e = calc_parser::program("1 +").unwrap_err();
+        assert_eq!(
+            expected_tokens(e.expected.tokens()),
+            ["number", "identifier", "(", "+", "-"]
+        );
+    }
+
+    #[test]
+    fn eval_errors() {
+        let input = "let x = 2\n1 + sqr(x)";
+        let program = calc_parser::program(input).unwrap();
+        let mut calculator = Calculator::new();
+        calculator.preset().unwrap();
+        let e = calculator.run(&program).unwrap_err();
+        let help = format!("did you mean `{}`?", calculator.suggest(&e).unwrap());
+        let rendered = Diagnostic::from_eval_error(&e)
+            .with_help(help)
+            .render(input, false);
+        assert_eq!(
+            rendered,
+            "error: unknown function `sqr`\n --> 2:5\n  |\n2 | 1 + sqr(x)\n  |     ^^^^^^\n  = help: did you mean `sqrt`?\n"
+        );
+
+        let rendered = Diagnostic::from_eval_error(&EvalError::StackUnderflow).render("", false);
+        assert_eq!(rendered, format!("error: {}\n", EvalError::StackUnderflow));
+    }
+
+    #[test]
+    fn color() {
+        let input = "1 +";
+        let diagnostic = parse_diagnostic(input);
+        assert!(!diagnostic.render(input, false).contains('\x1b'));
+        let rendered = diagnostic.render(input, true);
+        assert!(rendered.starts_with(&format!("{RED}error{RESET}")));
+        assert!(rendered.contains(&format!("{RED}^{RESET}")));
+    }
+
+    #[test]
+    fn levenshtein_distance() {
+        assert_eq!(levenshtein("", ""), 0);
+        assert_eq!(levenshtein("", "abc"), 3);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("log10", "log_10"), 1);
+        assert_eq!(levenshtein("日本", "本"), 1);
+    }
+
+    #[test]
+    fn suggestions() {
+        let names = ["sqrt", "sin", "sum", "log_10", "PI"];
+        assert_eq!(did_you_mean("sqr", names.into_iter()), Some("sqrt"));
+        assert_eq!(did_you_mean("log10", names.into_iter()), Some("log_10"));
+        assert_eq!(did_you_mean("pi", names.into_iter()), Some("PI"));
+        assert_eq!(did_you_mean("cos", names.into_iter()), None);
+        assert_eq!(did_you_mean("sum", names.into_iter()), None);
+
+        let names = ["E", "PI", "ans"];
+        assert_eq!(did_you_mean("x", names.into_iter()), None);
+        assert_eq!(did_you_mean("e", names.into_iter()), Some("E"));
+        assert_eq!(did_you_mean("P", names.into_iter()), None);
+        assert_eq!(did_you_mean("an", names.into_iter()), Some("ans"));
+    }
+}
@@ -0,0 +1,239 @@
+use crate::ast::Stmt;
+use crate::diagnostics::Diagnostic;
+use crate::parser::calc_parser;
+use crate::visitor::{Arity, Calculator, EvalError};
+use peg::error::ParseError;
+use peg::str::LineCol;
+use std::fmt;
+
+/// Errors of the embedding API.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    Parse(ParseError<LineCol>),
+    /// Problems found before running anything, such as calls with the wrong
+    /// number of arguments
+    Check(Vec<EvalError>),
+    Eval(EvalError),
+    /// The program only defines functions, so it has no value
+    NoValue,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse(e) => write!(f, "{e}"),
+            Error::Check(errors) => {
+                let messages: Vec<_> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", messages.join(", "))
+            }
+            Error::Eval(e) => write!(f, "{e}"),
+            Error::NoValue => write!(f, "the program has no value"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<EvalError> for Error {
+    fn from(e: EvalError) -> Self {
+        Error::Eval(e)
+    }
+}
+
+impl Error {
+    /// One diagnostic per problem, to be rendered against `source`.
+    pub fn diagnostics(&self, source: &str) -> Vec<Diagnostic> {
+        match self {
+            Error::Parse(e) => vec![Diagnostic::from_parse_error(source, e)],
+            Error::Check(errors) => errors.iter().map(Diagnostic::from_eval_error).collect(),
+            Error::Eval(e) => vec![Diagnostic::from_eval_error(e)],
+            Error::NoValue => vec![Diagnostic::new(self.to_string())],
+        }
+    }
+}
+
+/// Parses `input` into its statements.
+pub fn parse(input: &str) -> Result<Vec<Stmt>, Error> {
+    calc_parser::program(input).map_err(Error::Parse)
+}
+
+/// Evaluates `input` with the preset constants and functions, and returns the
+/// value of its last statement.
+///
+/// ```
+/// assert_eq!(rcalc::eval("let r = 2; PI * r ^ 2").unwrap(), std::f64::consts::PI * 4.0);
+/// ```
+pub fn eval(input: &str) -> Result<f64, Error> {
+    Engine::new().eval(input)
+}
+
+/// An interpreter session. Variables and functions, whether registered from
+/// Rust or defined by the programs it runs, stay visible to later programs.
+/// A clone is an independent session that starts from the same definitions.
+///
+/// ```
+/// use rcalc::{Arity, Engine};
+///
+/// let mut engine = Engine::new();
+/// engine.define_variable("rate", 0.05).unwrap();
+/// engine
+///     .define_function("compound", Arity::Fixed(2), |argv| Ok((1.0 + argv[0]).powf(argv[1])))
+///     .unwrap();
+/// let value = engine.eval("growth(years) = compound(rate, years); growth(2)").unwrap();
+/// assert!((value - 1.1025).abs() < 1e-12);
+/// // Definitions made by a program are kept
+/// assert!(engine.eval("growth(0)").unwrap() == 1.0);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Engine {
+    calculator: Calculator,
+}
+
+impl Engine {
+    /// Creates a session with the preset constants and functions.
+    pub fn new() -> Self {
+        let mut calculator = Calculator::new();
+        calculator.preset().expect("Presets are distinct");
+        Engine { calculator }
+    }
+
+    /// Creates a session without any predefined name.
+    pub fn empty() -> Self {
+        Engine {
+            calculator: Calculator::new(),
+        }
+    }
+
+    /// In strict mode, dividing by zero is an error instead of giving
+    /// infinity or NaN.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.calculator.set_strict(strict);
+    }
+
+    pub fn define_variable(&mut self, name: &str, value: f64) -> Result<(), Error> {
+        Ok(self.calculator.define_variable(name, value)?)
+    }
+
+    /// Registers a native function, `func` is only called with a number of
+    /// arguments that `arity` accepts.
+    pub fn define_function<F>(&mut self, name: &str, arity: Arity, func: F) -> Result<(), Error>
+    where
+        F: Fn(&[f64]) -> Result<f64, EvalError> + 'static,
+    {
+        Ok(self.calculator.define_function(name, arity, func)?)
+    }
+
+    /// Parses and runs `input`, and returns the value of its last statement.
+    pub fn eval(&mut self, input: &str) -> Result<f64, Error> {
+        let program = parse(input)?;
+        self.run(&program)?.ok_or(Error::NoValue)
+    }
+
+    /// Checks and runs `program`, and returns the value of its last statement
+    /// if it has one.
+    pub fn run(&mut self, program: &[Stmt]) -> Result<Option<f64>, Error> {
+        self.calculator
+            .arity_checker()
+            .check(program)
+            .map_err(Error::Check)?;
+        Ok(self.calculator.run(program)?)
+    }
+
+    /// A defined name close to the unknown one of `error`, if any.
+    pub fn suggest(&self, error: &EvalError) -> Option<&str> {
+        self.calculator.suggest(error)
+    }
+
+    /// Like [`Error::diagnostics`], and unknown names get a hint at the
+    /// closest name defined in the session.
+    pub fn diagnostics(&self, error: &Error, source: &str) -> Vec<Diagnostic> {
+        let with_help = |e: &EvalError| {
+            let diagnostic = Diagnostic::from_eval_error(e);
+            match self.suggest(e) {
+                Some(name) => diagnostic.with_help(format!("did you mean `{name}`?")),
+                None => diagnostic,
+            }
+        };
+        match error {
+            Error::Check(errors) => errors.iter().map(with_help).collect(),
+            Error::Eval(e) => vec![with_help(e)],
+            _ => error.diagnostics(source),
+        }
+    }
+
+    /// The underlying interpreter.
+    pub fn calculator(&self) -> &Calculator {
+        &self.calculator
+    }
+
+    pub fn calculator_mut(&mut self) -> &mut Calculator {
+        &mut self.calculator
+    }
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::assert_close;
+
+    #[test]
+    fn eval_input() {
+        assert_close(eval("1 + 2 * 3").unwrap(), 7.0);
+        assert_close(eval("f(x) = x + 1; f(f(1))").unwrap(), 3.0);
+        assert!(matches!(eval("1 +"), Err(Error::Parse(_))));
+        assert!(matches!(
+            eval("unknown + 1"),
+            Err(Error::Eval(EvalError::UnknownVariable { .. }))
+        ));
+        assert!(
+            matches!(eval("sin(1, 2) + cos()"), Err(Error::Check(errors)) if errors.len() == 2)
+        );
+        assert_eq!(eval("f(x) = x"), Err(Error::NoValue));
+    }
+
+    #[test]
+    fn session() {
+        let mut engine = Engine::new();
+        engine.define_variable("principal", 1000.0).unwrap();
+        engine
+            .define_function("twice", Arity::Fixed(1), |argv| Ok(argv[0] * 2.0))
+            .unwrap();
+        assert_close(engine.eval("let total = twice(principal)").unwrap(), 2000.0);
+        assert_close(engine.eval("total + 1").unwrap(), 2001.0);
+        assert!(matches!(
+            engine.define_variable("principal", 1.0),
+            Err(Error::Eval(EvalError::ReDefinition(_)))
+        ));
+
+        let mut engine = Engine::empty();
+        assert!(engine.eval("PI").is_err());
+        engine.set_strict(true);
+        assert!(matches!(
+            engine.eval("1 / 0"),
+            Err(Error::Eval(EvalError::DivisionByZero { .. }))
+        ));
+    }
+
+    #[test]
+    fn diagnostics() {
+        let input = "sqr(2)";
+        let e = eval(input).unwrap_err();
+        let diagnostics = e.diagnostics(input);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "unknown function `sqr`");
+
+        let e = eval("sin() + cos()").unwrap_err();
+        assert_eq!(e.diagnostics("sin() + cos()").len(), 2);
+
+        let engine = Engine::new();
+        let e = eval(input).unwrap_err();
+        let diagnostics = engine.diagnostics(&e, input);
+        assert_eq!(diagnostics[0].help.as_deref(), Some("did you mean `sqrt`?"));
+    }
+}
@@ -0,0 +1,355 @@
+//! Turns results into text, in the notation, precision and style asked for.
+//!
+//! ```
+//! use rcalc::format::{Grouping, Notation, NumberFormat};
+//!
+//! let format = NumberFormat {
+//!     notation: Notation::Eng,
+//!     sig_digits: Some(3),
+//!     ..NumberFormat::default()
+//! };
+//! assert_eq!(format.format(12345.0), "12.3e3");
+//!
+//! let format = NumberFormat {
+//!     grouping: Grouping::Underscore,
+//!     ..NumberFormat::default()
+//! };
+//! assert_eq!(format.format(1234567.0), "1_234_567");
+//! ```
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Notation {
+    /// The shortest form, or like `%g` when significant digits are given:
+    /// fixed for moderate magnitudes and scientific otherwise
+    #[default]
+    Auto,
+    /// Never an exponent
+    Fixed,
+    /// One digit before the point, `1.5e-3`
+    Sci,
+    /// Exponents are multiples of three, `0.015` is `15e-3`
+    Eng,
+}
+
+/// Separator between groups of three digits of the integer part.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Grouping {
+    #[default]
+    None,
+    /// `1_234_567`, as in Rust literals
+    Underscore,
+    /// `1,234,567`
+    Comma,
+    /// `1 234 567`
+    Space,
+}
+
+/// How NaN and infinities are spelled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NonFinite {
+    /// `NaN`, `inf` and `-inf`
+    #[default]
+    Rust,
+    /// `nan`, `inf` and `-inf`
+    C,
+    /// `NaN`, `Infinity` and `-Infinity`
+    Js,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NumberFormat {
+    pub notation: Notation,
+    /// Digits after the decimal point, of the mantissa in scientific and
+    /// engineering notation. The shortest exact form is used without it
+    pub precision: Option<usize>,
+    /// Significant digits, takes precedence over `precision`
+    pub sig_digits: Option<usize>,
+    pub grouping: Grouping,
+    pub non_finite: NonFinite,
+}
+
+impl NumberFormat {
+    pub fn format(&self, value: f64) -> String {
+        if !value.is_finite() {
+            return self.format_non_finite(value);
+        }
+        // Zero significant digits would say nothing about the value
+        let sig_digits = self.sig_digits.map(|digits| digits.max(1));
+        let text = match self.notation {
+            Notation::Auto => match (sig_digits, self.precision) {
+                (Some(digits), _) => general(value, digits),
+                (None, Some(precision)) => format!("{value:.precision$}"),
+                (None, None) => format!("{value}"),
+            },
+            Notation::Fixed => match (sig_digits, self.precision) {
+                (Some(digits), _) => {
+                    let (rounded, exponent) = round_sig(value, digits);
+                    let decimals = (digits as i32 - 1 - exponent).max(0) as usize;
+                    format!("{rounded:.decimals$}")
+                }
+                (None, Some(precision)) => format!("{value:.precision$}"),
+                (None, None) => format!("{value}"),
+            },
+            Notation::Sci => match (sig_digits, self.precision) {
+                (Some(digits), _) => format!("{value:.*e}", digits - 1),
+                (None, Some(precision)) => format!("{value:.precision$e}"),
+                (None, None) => format!("{value:e}"),
+            },
+            Notation::Eng => engineering(value, sig_digits, self.precision),
+        };
+        group(&text, self.grouping)
+    }
+
+    fn format_non_finite(&self, value: f64) -> String {
+        let sign = if value < 0.0 { "-" } else { "" };
+        match (self.non_finite, value.is_nan()) {
+            (NonFinite::Rust | NonFinite::Js, true) => "NaN".into(),
+            (NonFinite::C, true) => "nan".into(),
+            (NonFinite::Rust | NonFinite::C, false) => format!("{sign}inf"),
+            (NonFinite::Js, false) => format!("{sign}Infinity"),
+        }
+    }
+}
+
+/// Splits Rust's scientific form into its mantissa and exponent.
+fn split_exponent(sci: &str) -> (&str, i32) {
+    let (mantissa, exponent) = sci.split_once('e').expect("Scientific notation");
+    (mantissa, exponent.parse().expect("Integer exponent"))
+}
+
+/// `value` rounded to `digits` significant digits, and the decimal exponent
+/// of the rounded value.
+fn round_sig(value: f64, digits: usize) -> (f64, i32) {
+    let sci = format!("{value:.*e}", digits - 1);
+    let (_, exponent) = split_exponent(&sci);
+    (sci.parse().expect("Rust reads its own output"), exponent)
+}
+
+/// `%g`: scientific notation if the exponent is below -4 or not below the
+/// number of significant digits, and no trailing zeros.
+fn general(value: f64, digits: usize) -> String {
+    let (rounded, exponent) = round_sig(value, digits);
+    if exponent < -4 || exponent >= digits as i32 {
+        let sci = format!("{value:.*e}", digits - 1);
+        let (mantissa, exponent) = split_exponent(&sci);
+        format!("{}e{exponent}", trim_zeros(mantissa))
+    } else {
+        let decimals = (digits as i32 - 1 - exponent).max(0) as usize;
+        trim_zeros(&format!("{rounded:.decimals$}")).to_string()
+    }
+}
+
+fn trim_zeros(number: &str) -> &str {
+    if number.contains('.') {
+        number.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        number
+    }
+}
+
+/// Scientific notation with the exponent brought down to a multiple of
+/// three, the point moves right to make up for it.
+fn engineering(value: f64, sig_digits: Option<usize>, precision: Option<usize>) -> String {
+    let sci = |decimals: Option<usize>| match decimals {
+        Some(decimals) => format!("{value:.decimals$e}"),
+        None => format!("{value:e}"),
+    };
+    let shift = |exponent: i32| (exponent - exponent.div_euclid(3) * 3) as usize;
+    let mut text = sci(sig_digits.map(|digits| digits - 1));
+    if let (None, Some(precision)) = (sig_digits, precision) {
+        // The mantissa gets `shift` more digits before the point, so as many
+        // more are needed for `precision` of them to remain after it
+        let (_, exponent) = split_exponent(&text);
+        text = sci(Some(precision + shift(exponent)));
+        let (_, rounded_exponent) = split_exponent(&text);
+        // Rounding up to the next power of ten can change the shift
+        if shift(rounded_exponent) != shift(exponent) {
+            text = sci(Some(precision + shift(rounded_exponent)));
+        }
+    }
+
+    let (mantissa, exponent) = split_exponent(&text);
+    let (sign, mantissa) = match mantissa.strip_prefix('-') {
+        Some(mantissa) => ("-", mantissa),
+        None => ("", mantissa),
+    };
+    let mut digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+    let shift = shift(exponent);
+    let int_len = shift + 1;
+    if digits.len() < int_len {
+        digits.push_str(&"0".repeat(int_len - digits.len()));
+    }
+    let (int, frac) = digits.split_at(int_len);
+    let exponent = exponent - shift as i32;
+    if frac.is_empty() {
+        format!("{sign}{int}e{exponent}")
+    } else {
+        format!("{sign}{int}.{frac}e{exponent}")
+    }
+}
+
+/// Inserts the separator of `grouping` between groups of three digits of the
+/// integer part.
+fn group(text: &str, grouping: Grouping) -> String {
+    let separator = match grouping {
+        Grouping::None => return text.to_string(),
+        Grouping::Underscore => '_',
+        Grouping::Comma => ',',
+        Grouping::Space => ' ',
+    };
+    let (sign, rest) = match text.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", text),
+    };
+    let int_len = rest
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(rest.len());
+    let (int, tail) = rest.split_at(int_len);
+    let mut grouped = String::with_capacity(text.len() + int_len / 3);
+    grouped.push_str(sign);
+    for (i, digit) in int.chars().enumerate() {
+        if i > 0 && (int_len - i) % 3 == 0 {
+            grouped.push(separator);
+        }
+        grouped.push(digit);
+    }
+    grouped.push_str(tail);
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(
+        notation: Notation,
+        sig_digits: Option<usize>,
+        precision: Option<usize>,
+    ) -> NumberFormat {
+        NumberFormat {
+            notation,
+            sig_digits,
+            precision,
+            ..NumberFormat::default()
+        }
+    }
+
+    #[test]
+    fn auto() {
+        let shortest = NumberFormat::default();
+        assert_eq!(shortest.format(7.0), "7");
+        assert_eq!(shortest.format(0.25), "0.25");
+        assert_eq!(shortest.format(-0.1 - 0.2), "-0.30000000000000004");
+        assert_eq!(shortest.format(0.0), "0");
+        assert_eq!(shortest.format(1e16), "10000000000000000");
+        assert_eq!(shortest.format(0.000001), "0.000001");
+
+        let decimals = format(Notation::Auto, None, Some(3));
+        assert_eq!(decimals.format(std::f64::consts::PI), "3.142");
+        assert_eq!(decimals.format(2.0), "2.000");
+
+        let general = format(Notation::Auto, Some(3), None);
+        assert_eq!(general.format(std::f64::consts::PI), "3.14");
+        assert_eq!(general.format(2.0), "2");
+        assert_eq!(general.format(123.0), "123");
+        assert_eq!(general.format(1234.0), "1.23e3");
+        assert_eq!(general.format(999.9), "1e3");
+        assert_eq!(general.format(0.0001234), "0.000123");
+        assert_eq!(general.format(0.00001234), "1.23e-5");
+    }
+
+    #[test]
+    fn fixed() {
+        assert_eq!(
+            format(Notation::Fixed, None, None).format(1e20),
+            "100000000000000000000"
+        );
+        assert_eq!(
+            format(Notation::Fixed, None, Some(2)).format(1.005e3),
+            "1005.00"
+        );
+        let sig = format(Notation::Fixed, Some(2), None);
+        assert_eq!(sig.format(123456.0), "120000");
+        assert_eq!(sig.format(-0.00012345), "-0.00012");
+        assert_eq!(sig.format(9.96), "10");
+        // Significant digits win over decimals
+        assert_eq!(
+            format(Notation::Fixed, Some(3), Some(5)).format(2.0),
+            "2.00"
+        );
+    }
+
+    #[test]
+    fn scientific() {
+        assert_eq!(format(Notation::Sci, None, None).format(1234.5), "1.2345e3");
+        assert_eq!(
+            format(Notation::Sci, None, Some(2)).format(-0.0012345),
+            "-1.23e-3"
+        );
+        assert_eq!(format(Notation::Sci, Some(1), None).format(987.0), "1e3");
+        assert_eq!(format(Notation::Sci, None, None).format(0.0), "0e0");
+    }
+
+    #[test]
+    fn engineering() {
+        let shortest = format(Notation::Eng, None, None);
+        assert_eq!(shortest.format(1234.5), "1.2345e3");
+        assert_eq!(shortest.format(12345.0), "12.345e3");
+        assert_eq!(shortest.format(123456.0), "123.456e3");
+        assert_eq!(shortest.format(100000.0), "100e3");
+        assert_eq!(shortest.format(-0.0015), "-1.5e-3");
+        assert_eq!(shortest.format(0.00015), "150e-6");
+        assert_eq!(shortest.format(42.0), "42e0");
+
+        let sig = format(Notation::Eng, Some(3), None);
+        assert_eq!(sig.format(12345.0), "12.3e3");
+        assert_eq!(sig.format(1e5), "100e3");
+        assert_eq!(sig.format(999999.0), "1.00e6");
+
+        let decimals = format(Notation::Eng, None, Some(1));
+        assert_eq!(decimals.format(12345.0), "12.3e3");
+        assert_eq!(decimals.format(0.0047), "4.7e-3");
+        // 999.96 rounds to 1000.0, which moves to the next exponent
+        assert_eq!(decimals.format(999.96), "1.0e3");
+    }
+
+    #[test]
+    fn grouping() {
+        let grouped = |grouping, value| {
+            NumberFormat {
+                grouping,
+                ..NumberFormat::default()
+            }
+            .format(value)
+        };
+        assert_eq!(grouped(Grouping::Underscore, 1234567.0), "1_234_567");
+        assert_eq!(grouped(Grouping::Comma, -1234567.891), "-1,234,567.891");
+        assert_eq!(grouped(Grouping::Space, 123456.0), "123 456");
+        assert_eq!(grouped(Grouping::Comma, 123.0), "123");
+        assert_eq!(grouped(Grouping::None, 1234.0), "1234");
+        assert_eq!(grouped(Grouping::Comma, 1e9), "1,000,000,000");
+
+        let fixed = NumberFormat {
+            notation: Notation::Fixed,
+            precision: Some(2),
+            grouping: Grouping::Comma,
+            ..NumberFormat::default()
+        };
+        assert_eq!(fixed.format(1e6), "1,000,000.00");
+    }
+
+    #[test]
+    fn non_finite() {
+        let spelled = |non_finite| {
+            let format = NumberFormat {
+                non_finite,
+                sig_digits: Some(3),
+                ..NumberFormat::default()
+            };
+            [f64::NAN, f64::INFINITY, f64::NEG_INFINITY].map(|value| format.format(value))
+        };
+        assert_eq!(spelled(NonFinite::Rust), ["NaN", "inf", "-inf"]);
+        assert_eq!(spelled(NonFinite::C), ["nan", "inf", "-inf"]);
+        assert_eq!(spelled(NonFinite::Js), ["NaN", "Infinity", "-Infinity"]);
+    }
+}
@@ -0,0 +1,36 @@
+//! A calculator language with an interpreter and an optional LLVM JIT
+//! backend, behind the `jit` feature.
+//!
+//! The quickest way in is [`eval`], [`Engine`] keeps variables and functions
+//! between programs and lets the host register its own:
+//!
+//! ```
+//! assert_eq!(rcalc::eval("1 + 2 * 3").unwrap(), 7.0);
+//!
+//! let mut engine = rcalc::Engine::new();
+//! engine.define_variable("principal", 1000.0).unwrap();
+//! assert_eq!(engine.eval("principal * 2").unwrap(), 2000.0);
+//! ```
+//!
+//! Lower level building blocks are exported too: [`parse`] gives the AST of
+//! [`ast`], which the visitors of [`visitor`] walk.
+
+#[cfg(feature = "jit")]
+pub mod aot;
+pub mod ast;
+pub mod diagnostics;
+mod engine;
+pub mod format;
+pub mod parser;
+pub mod symbols;
+#[cfg(test)]
+mod utils;
+pub mod visitor;
+
+pub use ast::{
+    Assignment, Atom, BinaryArithmetic, BinaryOp, Expr, FunctionCall, FunctionDef, Span, Stmt,
+    UnaryArithmetic, UnaryOp,
+};
+pub use diagnostics::Diagnostic;
+pub use engine::{eval, parse, Engine, Error};
+pub use visitor::{Arity, EvalError};
@@ -1,113 +1,465 @@
-mod ast;
+mod batch;
 mod cli;
-mod parser;
-mod symbols;
-mod utils;
-mod visitor;
+mod output;
+mod repl;
 
-use clap::Parser;
-use cli::{get_variables, Cli};
+use clap::{Parser, ValueEnum};
+use cli::{get_variables, Cli, Command, CompileArgs, Input, Output};
 #[cfg(feature = "jit")]
 use inkwell::context::Context;
-use parser::calc_parser;
-use std::error::Error;
 #[cfg(feature = "jit")]
-use visitor::CalculatorJIT;
-use visitor::{Calculator, PrettyPrinter, Visitor};
+use inkwell::OptimizationLevel;
+use output::{Backend, Evaluation, Failure, Timings};
+#[cfg(feature = "jit")]
+use rcalc::aot::{self, AotError};
+#[cfg(feature = "jit")]
+use rcalc::visitor::{CalculatorJIT, Checkpoint};
+use rcalc::visitor::{PrettyPrinter, Visitor};
+use rcalc::{Diagnostic, Engine, Error, Stmt};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+/// Exit status of rcalc, listed in `--help`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Exit {
+    Success = 0,
+    /// The program does not parse
+    Parse = 1,
+    /// The program fails to evaluate, or to pass the checks before running
+    Eval = 2,
+    /// The requested backend is not built in or cannot be used
+    Backend = 3,
+    /// A bad option, variable or file
+    Usage = 4,
+}
+
+impl Exit {
+    fn of(error: &Error) -> Self {
+        match error {
+            Error::Parse(_) => Exit::Parse,
+            Error::Check(_) | Error::Eval(_) | Error::NoValue => Exit::Eval,
+        }
+    }
+}
+
+impl From<Exit> for ExitCode {
+    fn from(exit: Exit) -> Self {
+        ExitCode::from(exit as u8)
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) => {
+            // Help and version are printed on stdout, errors on stderr
+            let _ = e.print();
+            if !e.use_stderr() {
+                return Exit::Success.into();
+            }
+            if requested_output() == Output::Json {
+                println!("{}", output::error_json("usage", &usage_message(&e)));
+            }
+            return Exit::Usage.into();
+        }
+    };
+    let color = cli.color.enabled(&std::io::stderr());
+    if let Some(Command::Compile(args)) = &cli.command {
+        return compile(args, color).into();
+    }
+    if cli.jit && !cfg!(feature = "jit") {
+        let message = "`--jit` needs rcalc to be built with feature `jit`";
+        report_error(cli.output, "backend", message, color);
+        return Exit::Backend.into();
+    }
+    let variables = match get_variables(&cli) {
+        Ok(variables) => variables,
+        Err(e) => {
+            report_error(cli.output, "usage", &e, color);
+            return Exit::Usage.into();
+        }
+    };
+
+    let Some(input) = Input::from_cli(&cli) else {
+        let settings = repl::Settings {
+            jit: cli.jit,
+            format: cli.number_format(),
+            strict: cli.strict,
+            color,
+        };
+        return repl::run(settings, variables);
+    };
+    if cli.lines {
+        return run_lines(&cli, &variables, input, color).into();
+    }
+    let source = match read_input(&input) {
+        Ok(source) => source,
+        Err(e) => {
+            report_error(cli.output, "io", &e, color);
+            return Exit::Usage.into();
+        }
+    };
+    let evaluation = evaluate(&cli, &mut prepare(&cli, &variables), &source);
+    if let Err(failure) = &evaluation.result {
+        report(&source, &failure.diagnostics, color);
+    }
+    if cli.output == Output::Json {
+        println!(
+            "{}",
+            evaluation.to_json(&source, 1, &cli.number_format(), cli.verbose)
+        );
+    }
+    let exit = match &evaluation.result {
+        Ok(_) => Exit::Success,
+        Err(failure) => Exit::of(&failure.error),
+    };
+    // A program made only of function definitions has nothing to print
+    let Ok(Some(result)) = evaluation.result else {
+        return exit.into();
+    };
+    if cli.output == Output::Json {
+        return exit.into();
+    }
+    if !cli.pure {
+        print!("{} result: ", evaluation.backend.label());
+    }
+    print!("{}", cli.number_format().format(result));
+
+    if !cli.pure {
+        println!();
+    }
+    Exit::Success.into()
+}
+
+/// Reads the whole program of a script or of standard input.
+fn read_input(input: &Input) -> Result<String, String> {
+    match input {
+        Input::Expr(expr) => Ok(expr.clone()),
+        Input::Stdin => io::read_to_string(io::stdin())
+            .map_err(|e| format!("failed to read standard input: {e}")),
+        Input::File(path) => std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read `{}`: {e}", path.display())),
+    }
+}
+
+/// Evaluates each line of the input with `--lines`. The run only fails if a
+/// line aborts it.
+fn run_lines(cli: &Cli, variables: &[(String, f64)], input: Input, color: bool) -> Exit {
+    let reader: Box<dyn BufRead> = match input {
+        Input::Expr(expr) => Box::new(io::Cursor::new(expr)),
+        Input::Stdin => Box::new(io::stdin().lock()),
+        Input::File(path) => match File::open(&path) {
+            Ok(file) => Box::new(BufReader::new(file)),
+            Err(e) => {
+                let message = format!("failed to read `{}`: {e}", path.display());
+                report_error(cli.output, "io", &message, color);
+                return Exit::Usage;
+            }
+        },
+    };
+    let options = batch::Options {
+        output: cli.output,
+        on_error: cli.on_error,
+        marker: &cli.error_marker,
+        format: cli.number_format(),
+        with_ast: cli.verbose,
+        color,
+    };
+    let mut prepared = prepare(cli, variables);
+    let aborted = batch::run(
+        reader,
+        &mut io::stdout(),
+        &mut io::stderr(),
+        &options,
+        |line| evaluate(cli, &mut prepared, line),
+    );
+    match aborted {
+        Ok(None) => Exit::Success,
+        Ok(Some(failure)) => Exit::of(&failure.error),
+        // The reader of the results went away, as `head` does
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Exit::Success,
+        Err(e) => {
+            report_error(cli.output, "io", &e.to_string(), color);
+            Exit::Usage
+        }
+    }
+}
+
+/// The presets and the variables of the command line, defined once for all
+/// the programs of a run. Every program starts from them, so the lines of
+/// `--lines` do not see each other's definitions.
+enum Prepared {
+    Interpreter(Engine),
+    #[cfg(feature = "jit")]
+    Jit(CalculatorJIT<'static>, Checkpoint<'static>),
+}
+
+/// Sets up the backend chosen on the command line.
+fn prepare(cli: &Cli, variables: &[(String, f64)]) -> Prepared {
+    if !cli.jit {
+        let mut engine = Engine::new();
+        engine.set_strict(cli.strict);
+        // User defined variables and functions
+        // engine.define_variable("a", 222.0).unwrap();
+        // engine
+        //     .define_function("mul", Arity::Fixed(2), |args| Ok(args[0] * args[1]))
+        //     .unwrap();
+        for (name, value) in variables {
+            engine
+                .define_variable(name, *value)
+                .expect("Variables are distinct and do not shadow presets");
+        }
+        Prepared::Interpreter(engine)
+    } else {
+        #[cfg(not(feature = "jit"))]
+        {
+            unreachable!("`--jit` is rejected without feature `jit`");
+        }
+        #[cfg(feature = "jit")]
+        {
+            // JIT calc
+            let mut calculator_jit =
+                CalculatorJIT::with_opt_level(jit_context(), opt_level(cli.opt_level));
+            let text = cli.output == Output::Text;
+            calculator_jit
+                .set_record_listing(text && (cli.verbose || cli.emit_llvm || cli.emit_asm));
+            calculator_jit.preset().unwrap();
+            // User defined variables and functions
+            // calculator_jit.define_variable(&"a".into(), 222.0).unwrap();
+            // calculator_jit
+            //     .define_function(&"mul".into(), 2, |args, builder| {
+            //         let a = args[0];
+            //         let b = args[1];
+            //         builder.build_float_mul(a, b, "mul")
+            //     })
+            //     .unwrap();
+            for (name, value) in variables {
+                calculator_jit
+                    .define_variable(name, *value)
+                    .expect("Variables are distinct and do not shadow presets");
+            }
+            let checkpoint = calculator_jit.checkpoint();
+            Prepared::Jit(calculator_jit, checkpoint)
+        }
+    }
+}
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let cli = Cli::parse();
-    let variables = get_variables(&cli);
+/// The context of every JIT session of the thread. Sessions live as long as
+/// the process, so it is leaked once instead of every time one is created.
+#[cfg(feature = "jit")]
+fn jit_context() -> &'static Context {
+    thread_local! {
+        static CONTEXT: &'static Context = Box::leak(Box::new(Context::create()));
+    }
+    CONTEXT.with(|context| *context)
+}
 
-    let input = cli.expr;
+/// Parses and runs the program `input` with the backend chosen on the
+/// command line.
+fn evaluate(cli: &Cli, prepared: &mut Prepared, input: &str) -> Evaluation {
+    let backend = if cli.jit {
+        Backend::Jit
+    } else {
+        Backend::Interpreter
+    };
+    let start = Instant::now();
     // parse to AST
-    match calc_parser::expr(&input) {
-        Ok(parsed_input) => {
+    let parsed = rcalc::parse(input);
+    let parse = start.elapsed();
+    let parsed_input = match parsed {
+        Ok(parsed_input) => parsed_input,
+        Err(error) => {
+            return Evaluation {
+                backend,
+                program: None,
+                result: Err(Failure {
+                    diagnostics: error.diagnostics(input),
+                    error,
+                }),
+                timings: Timings {
+                    parse,
+                    eval: Duration::ZERO,
+                },
+            };
+        }
+    };
+    let text = cli.output == Output::Text;
+    if cli.verbose && !cli.pure && text {
+        // pretty printer
+        let mut printer = PrettyPrinter::new(2);
+        let separator = "~".repeat(20);
+        println!("{separator}");
+        println!("AST:");
+        for stmt in &parsed_input {
+            printer.visit_stmt(stmt);
+        }
+        print!("{}", printer.finish());
+        println!("{separator}");
+    }
+
+    let start = Instant::now();
+    let result = execute(prepared, input, &parsed_input);
+    let eval = start.elapsed();
+    #[cfg(feature = "jit")]
+    if let Prepared::Jit(calculator_jit, _) = prepared {
+        // The listing of an earlier line is left over when compiling fails
+        if let Some(listing) = calculator_jit.listing().filter(|_| result.is_ok()) {
             if cli.verbose && !cli.pure {
-                // pretty printer
-                let mut printer = PrettyPrinter::new(2);
                 let separator = "~".repeat(20);
+                println!("Unoptimized LLVM IR:");
+                print!("{}", listing.unoptimized);
                 println!("{separator}");
-                println!("AST:");
-                printer.visit_expr(&parsed_input);
+                println!("Optimized LLVM IR:");
+                print!("{}", listing.optimized);
                 println!("{separator}");
             }
-
-            let result;
-            if !cli.jit {
-                // calc
-                let mut calculator = Calculator::new();
-                calculator.preset().unwrap();
-                // User defined variables and functions
-                // calculator.define_variable(&"a".into(), 222.0).unwrap();
-                // calculator
-                //     .define_function(&"mul".into(), |args| args[0] * args[1])
-                //     .unwrap();
-                for (name, value) in variables {
-                    calculator.define_variable(&name, value).unwrap();
-                }
-                calculator.visit_expr(&parsed_input);
-                result = calculator.result().unwrap();
-
-                if !cli.pure {
-                    print!("Calculator Interpret result: ");
-                };
-            } else {
-                #[cfg(not(feature = "jit"))]
-                {
-                    panic!("Unable to use feature `jit`");
-                }
-                #[cfg(feature = "jit")]
-                {
-                    // JIT calc
-                    let context = Context::create();
-                    let mut calculator_jit = CalculatorJIT::new(&context);
-                    calculator_jit.preset().unwrap();
-                    // User defined variables and functions
-                    // calculator_jit.define_variable(&"a".into(), 222.0).unwrap();
-                    // calculator_jit
-                    //     .define_function(&"mul".into(), 2, |args, builder| {
-                    //         let a = args[0];
-                    //         let b = args[1];
-                    //         builder.build_float_mul(a, b, "mul")
-                    //     })
-                    //     .unwrap();
-                    for (name, value) in variables {
-                        calculator_jit.define_variable(&name, value).unwrap();
-                    }
-                    let calc_main = calculator_jit.compile(&parsed_input).unwrap();
-                    result = unsafe { calc_main.call() };
-
-                    if !cli.pure {
-                        print!("JIT compile result: ");
-                    };
-                }
+            if cli.emit_llvm {
+                print!("{}", listing.optimized);
             }
-            match cli.precision {
-                Some(precision) => {
-                    print!("{result:.precision$}")
-                }
-                None => print!("{result}"),
+            if cli.emit_asm {
+                print!("{}", listing.assembly);
             }
+        }
+    }
+    Evaluation {
+        backend,
+        program: Some(parsed_input),
+        result,
+        timings: Timings { parse, eval },
+    }
+}
 
-            if !cli.pure {
-                println!();
-            }
-            Ok(())
+/// Runs a parsed program, and returns the value of its last statement if it
+/// has one.
+fn execute(
+    prepared: &mut Prepared,
+    input: &str,
+    parsed_input: &[Stmt],
+) -> Result<Option<f64>, Failure> {
+    match prepared {
+        Prepared::Interpreter(presets) => {
+            // calc
+            let mut engine = presets.clone();
+            engine.run(parsed_input).map_err(|error| Failure {
+                diagnostics: engine.diagnostics(&error, input),
+                error,
+            })
         }
+        #[cfg(feature = "jit")]
+        Prepared::Jit(calculator_jit, checkpoint) => {
+            // JIT calc
+            calculator_jit.restore(checkpoint);
+            let calc_main = match calculator_jit.compile_program(parsed_input) {
+                Ok(calc_main) => calc_main,
+                Err(errors) => {
+                    let error = Error::Check(errors);
+                    return Err(Failure {
+                        diagnostics: error.diagnostics(input),
+                        error,
+                    });
+                }
+            };
+            let has_value = parsed_input
+                .iter()
+                .any(|stmt| !matches!(stmt, Stmt::Function(_)));
+            let result = has_value.then(|| unsafe { calc_main.call() });
+
+            Ok(result)
+        }
+    }
+}
+
+/// Ahead-of-time compiles the formula of `rcalc compile` and writes its header.
+#[cfg(feature = "jit")]
+fn compile(args: &CompileArgs, color: bool) -> Exit {
+    let params: Vec<&str> = args.params.iter().map(String::as_str).collect();
+    let compiled = aot::compile(
+        &args.expr,
+        &params,
+        &args.name,
+        &args.output,
+        opt_level(args.opt_level),
+    );
+    let header_path = args
+        .header
+        .clone()
+        .unwrap_or_else(|| aot::default_header_path(&args.output));
+    let written = compiled.and_then(|()| {
+        std::fs::write(&header_path, aot::header(&args.name, &params)).map_err(AotError::from)
+    });
+    let error = match written {
+        Ok(()) => return Exit::Success,
+        Err(AotError::Parse(e)) => Error::Parse(e),
+        Err(AotError::Eval(errors)) => Error::Check(errors),
         Err(e) => {
-            let (err_line, err_col) = (e.location.line, e.location.column);
-            let error_line = input.split('\n').collect::<Vec<_>>()[err_line - 1];
-            println!(
-                "Unexpected char `{}` at line {}, column {}:",
-                error_line.chars().nth(err_col - 1).unwrap(),
-                err_line,
-                err_col
-            );
-            println!("{}", error_line);
-            println!("{}^", " ".repeat(err_col - 1));
-            println!("Excepct chars: {:?}", e.expected);
-            Ok(())
+            report("", &[Diagnostic::new(e.to_string())], color);
+            return match e {
+                AotError::Codegen(_) | AotError::Link(_) => Exit::Backend,
+                _ => Exit::Usage,
+            };
+        }
+    };
+    report(&args.expr, &error.diagnostics(&args.expr), color);
+    Exit::of(&error)
+}
+
+#[cfg(not(feature = "jit"))]
+fn compile(_args: &CompileArgs, color: bool) -> Exit {
+    let diagnostic = Diagnostic::new("`compile` needs rcalc to be built with feature `jit`");
+    report("", &[diagnostic], color);
+    Exit::Backend
+}
+
+#[cfg(feature = "jit")]
+fn opt_level(level: u8) -> OptimizationLevel {
+    match level {
+        0 => OptimizationLevel::None,
+        1 => OptimizationLevel::Less,
+        2 => OptimizationLevel::Default,
+        _ => OptimizationLevel::Aggressive,
+    }
+}
+
+/// The `--output` of a command line that clap rejected. clap stops at the
+/// first bad argument, so the arguments are looked through by hand.
+fn requested_output() -> Output {
+    let mut args = std::env::args().skip(1).take_while(|arg| arg != "--");
+    let mut output = Output::Text;
+    while let Some(arg) = args.next() {
+        let value = match arg.strip_prefix("--output") {
+            Some("") => args.next(),
+            Some(value) => value.strip_prefix('=').map(str::to_string),
+            None => continue,
+        };
+        if let Some(value) = value.and_then(|value| Output::from_str(&value, false).ok()) {
+            output = value;
         }
     }
+    output
+}
+
+/// The first line of a clap error, without its `error: ` prefix.
+fn usage_message(e: &clap::Error) -> String {
+    let rendered = e.to_string();
+    let first_line = rendered.lines().next().unwrap_or_default();
+    first_line
+        .strip_prefix("error: ")
+        .unwrap_or(first_line)
+        .to_string()
+}
+
+/// Reports an error that is not about a program, such as an unreadable file.
+fn report_error(output: Output, kind: &str, message: &str, color: bool) {
+    report("", &[Diagnostic::new(message)], color);
+    if output == Output::Json {
+        println!("{}", output::error_json(kind, message));
+    }
+}
+
+fn report(input: &str, diagnostics: &[Diagnostic], color: bool) {
+    for diagnostic in diagnostics {
+        eprint!("{}", diagnostic.render(input, color));
+    }
 }
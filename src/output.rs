@@ -0,0 +1,231 @@
+use rcalc::ast::{Span, Stmt};
+use rcalc::diagnostics::{expected_tokens, location};
+use rcalc::format::NumberFormat;
+use rcalc::visitor::JsonPrinter;
+use rcalc::{Diagnostic, Error};
+use serde_json::{json, Map, Value};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Interpreter,
+    Jit,
+}
+
+impl Backend {
+    /// The name shown in front of a result.
+    pub fn label(self) -> &'static str {
+        match self {
+            Backend::Interpreter => "Calculator Interpret",
+            Backend::Jit => "JIT compile",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Backend::Interpreter => "interpreter",
+            Backend::Jit => "jit",
+        }
+    }
+}
+
+/// A program that could not be run, and what to show about it.
+#[derive(Debug)]
+pub struct Failure {
+    pub error: Error,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Timings {
+    pub parse: Duration,
+    /// Includes code generation with the JIT backend
+    pub eval: Duration,
+}
+
+/// The outcome of running a program.
+#[derive(Debug)]
+pub struct Evaluation {
+    pub backend: Backend,
+    /// `None` if the program does not parse
+    pub program: Option<Vec<Stmt>>,
+    pub result: Result<Option<f64>, Failure>,
+    pub timings: Timings,
+}
+
+impl Evaluation {
+    /// The evaluation of `input` as a JSON object, with the result spelled
+    /// in `format` as well. `first_line` is the line of a larger input that
+    /// `input` starts at, and the syntax tree is only included `with_ast`.
+    pub fn to_json(
+        &self,
+        input: &str,
+        first_line: usize,
+        format: &NumberFormat,
+        with_ast: bool,
+    ) -> Value {
+        let (result, formatted, error) = match &self.result {
+            Ok(Some(value)) => (json!(value), json!(format.format(*value)), Value::Null),
+            Ok(None) => (Value::Null, Value::Null, Value::Null),
+            Err(failure) => (
+                Value::Null,
+                Value::Null,
+                failure_json(failure, input, first_line),
+            ),
+        };
+        let mut object = Map::new();
+        object.insert("input".into(), json!(input));
+        object.insert("success".into(), json!(self.result.is_ok()));
+        // Not finite values have no JSON number, `formatted` spells them
+        object.insert("result".into(), result);
+        object.insert("formatted".into(), formatted);
+        object.insert("backend".into(), json!(self.backend.name()));
+        object.insert(
+            "timings".into(),
+            json!({
+                "parse_ms": milliseconds(self.timings.parse),
+                "eval_ms": milliseconds(self.timings.eval),
+            }),
+        );
+        object.insert("error".into(), error);
+        if with_ast {
+            let ast = match &self.program {
+                Some(program) => JsonPrinter::new().program(program),
+                None => Value::Null,
+            };
+            object.insert("ast".into(), ast);
+        }
+        Value::Object(object)
+    }
+}
+
+/// A failure outside of any program, such as an unreadable file.
+pub fn error_json(kind: &str, message: &str) -> Value {
+    json!({
+        "success": false,
+        "error": {"kind": kind, "message": message, "diagnostics": []},
+    })
+}
+
+/// The first diagnostic describes the error, all of them are listed under
+/// `diagnostics`. Parse errors list the tokens that were expected.
+fn failure_json(failure: &Failure, input: &str, first_line: usize) -> Value {
+    let (kind, expected) = match &failure.error {
+        Error::Parse(e) => ("parse", expected_tokens(e.expected.tokens())),
+        Error::Check(_) => ("check", Vec::new()),
+        Error::Eval(_) | Error::NoValue => ("eval", Vec::new()),
+    };
+    let diagnostics: Vec<_> = failure
+        .diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic_json(diagnostic, input, first_line))
+        .collect();
+    let first = failure.diagnostics.first();
+    json!({
+        "kind": kind,
+        "message": first.map_or_else(|| failure.error.to_string(), |d| d.message.clone()),
+        "span": first.and_then(|d| d.span).map(|span| span_json(span, input, first_line)),
+        "expected": expected,
+        "diagnostics": diagnostics,
+    })
+}
+
+fn diagnostic_json(diagnostic: &Diagnostic, input: &str, first_line: usize) -> Value {
+    json!({
+        "message": diagnostic.message,
+        "span": diagnostic.span.map(|span| span_json(span, input, first_line)),
+        "label": diagnostic.label,
+        "help": diagnostic.help,
+    })
+}
+
+/// Byte offsets, and the 1-based line and column of the start.
+fn span_json(span: Span, input: &str, first_line: usize) -> Value {
+    let (line, column) = location(input, span.start);
+    json!({
+        "start": span.start,
+        "end": span.end,
+        "line": line + first_line - 1,
+        "column": column,
+    })
+}
+
+fn milliseconds(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Cli;
+    use clap::Parser;
+
+    fn evaluation(input: &str) -> Evaluation {
+        let cli = Cli::parse_from(["rcalc", "--output", "json"]);
+        crate::evaluate(&cli, &mut crate::prepare(&cli, &[]), input)
+    }
+
+    #[test]
+    fn success() {
+        let format = NumberFormat {
+            precision: Some(1),
+            ..NumberFormat::default()
+        };
+        let object = evaluation("1 / 4").to_json("1 / 4", 1, &format, false);
+        assert_eq!(object["input"], "1 / 4");
+        assert_eq!(object["success"], true);
+        assert_eq!(object["result"], 0.25);
+        assert_eq!(object["formatted"], "0.2");
+        assert_eq!(object["backend"], "interpreter");
+        assert!(object["timings"]["parse_ms"].is_f64());
+        assert!(object["timings"]["eval_ms"].is_f64());
+        assert_eq!(object["error"], Value::Null);
+        assert!(object.get("ast").is_none());
+
+        let object = evaluation("1 / 0").to_json("1 / 0", 1, &NumberFormat::default(), true);
+        assert_eq!(object["result"], Value::Null);
+        assert_eq!(object["formatted"], "inf");
+        assert_eq!(object["ast"][0]["expr"]["op"], "Div");
+
+        let object = evaluation("f(x) = x").to_json("f(x) = x", 1, &NumberFormat::default(), false);
+        assert_eq!(object["success"], true);
+        assert_eq!(object["result"], Value::Null);
+    }
+
+    #[test]
+    fn errors() {
+        let input = "1 +";
+        let object = evaluation(input).to_json(input, 1, &NumberFormat::default(), true);
+        assert_eq!(object["success"], false);
+        assert_eq!(object["ast"], Value::Null);
+        let error = &object["error"];
+        assert_eq!(error["kind"], "parse");
+        assert_eq!(error["message"], "unexpected end of input");
+        assert_eq!(
+            error["span"],
+            json!({"start": 3, "end": 3, "line": 1, "column": 4})
+        );
+        assert_eq!(
+            error["expected"],
+            json!(["number", "identifier", "(", "+", "-"])
+        );
+
+        let input = "let x = 1\nsqr(x)";
+        let object = evaluation(input).to_json(input, 5, &NumberFormat::default(), false);
+        let error = &object["error"];
+        assert_eq!(error["kind"], "eval");
+        assert_eq!(error["message"], "unknown function `sqr`");
+        assert_eq!(error["span"]["line"], 6);
+        assert_eq!(error["expected"], json!([]));
+        assert_eq!(error["diagnostics"][0]["help"], "did you mean `sqrt`?");
+
+        let input = "sin() + cos(1, 2)";
+        let object = evaluation(input).to_json(input, 1, &NumberFormat::default(), false);
+        assert_eq!(object["error"]["kind"], "check");
+        assert_eq!(object["error"]["diagnostics"].as_array().unwrap().len(), 2);
+
+        let object = error_json("io", "failed to read `x.calc`");
+        assert_eq!(object["success"], false);
+        assert_eq!(object["error"]["kind"], "io");
+    }
+}
@@ -1,11 +1,39 @@
-use crate::ast::{Atom, BinaryArithmetic, BinaryOp, Expr, FunctionCall, UnaryArithmetic, UnaryOp};
+use crate::ast::{
+    Assignment, Atom, BinaryArithmetic, BinaryOp, Expr, FunctionCall, FunctionDef, Span, Stmt,
+    UnaryArithmetic, UnaryOp,
+};
+
+/// Words the grammar gives a meaning to. They parse as identifiers where a
+/// keyword cannot stand, but a variable named after one is ambiguous.
+pub const KEYWORDS: &[&str] = &["let", "rem"];
 
 peg::parser! {
     pub grammar calc_parser() for str {
         use super::Expr;
 
-        pub rule program() -> Expr
-            = __ e:expr() __ { e }
+        pub rule program() -> Vec<Stmt>
+            = __ s:(statement() ++ separator()) separator()? __ { s }
+
+        pub rule statement() -> Stmt
+            = start:position!() "let" !ident_char() _ id:name() _ "=" _ e:expr() end:position!() {
+                Stmt::Let(Assignment::new(id, e).with_span(Span::new(start, end)))
+            }
+            / function_def()
+            / start:position!() id:name() _ "=" _ e:expr() end:position!() {
+                Stmt::Assign(Assignment::new(id, e).with_span(Span::new(start, end)))
+            }
+            / e:expr() { e.into() }
+
+        pub rule function_def() -> Stmt
+            = start:position!() id:name() _ params:bracketed(<commasep(<name()>)>) _ "=" _ e:expr() end:position!() {?
+                if (1..params.len()).any(|i| params[i..].contains(&params[i - 1])) {
+                    Err("distinct parameter names")
+                } else {
+                    Ok(Stmt::Function(FunctionDef::new(id, params, e).with_span(Span::new(start, end))))
+                }
+            }
+
+        rule separator() = (_ (";" / "\r"? "\n"))+ _
 
         #[cache_left_rec]
         pub rule expr() -> Expr
@@ -16,14 +44,35 @@ peg::parser! {
         #[cache_left_rec]
         pub rule term() -> Expr
             = a:term() _ "*" _ b:factor_with_unary_op() { BinaryArithmetic::new(BinaryOp::Mul, a, b).into() }
+            / a:term() _ "//" _ b:factor_with_unary_op() { BinaryArithmetic::new(BinaryOp::FloorDiv, a, b).into() }
             / a:term() _ "/" _ b:factor_with_unary_op() { BinaryArithmetic::new(BinaryOp::Div, a, b).into() }
+            / a:term() _ "%" _ b:factor_with_unary_op() { BinaryArithmetic::new(BinaryOp::Mod, a, b).into() }
+            / a:term() _ "rem" !ident_char() _ b:factor_with_unary_op() { BinaryArithmetic::new(BinaryOp::Rem, a, b).into() }
             / factor_with_unary_op()
 
-        #[cache_left_rec]
+        #[cache]
         pub rule factor_with_unary_op() -> Expr
-            = "+" _ a:factor_with_unary_op() { UnaryArithmetic::new(UnaryOp::Pos, a).into() }
-            / "-" _ a:factor_with_unary_op() { UnaryArithmetic::new(UnaryOp::Neg, a).into() }
-            / a:factor_with_unary_op() "!" { UnaryArithmetic::new(UnaryOp::Fac, a).into() }
+            = start:position!() "+" _ a:factor_with_unary_op() {
+                let span = Span::new(start, a.span().end);
+                UnaryArithmetic::new(UnaryOp::Pos, a).with_span(span).into()
+            }
+            / start:position!() "-" _ a:factor_with_unary_op() {
+                let span = Span::new(start, a.span().end);
+                UnaryArithmetic::new(UnaryOp::Neg, a).with_span(span).into()
+            }
+            / power()
+
+        // Right-associative, and the exponent may carry its own sign: `2^-1`
+        pub rule power() -> Expr
+            = a:factor_with_postfix_op() _ ("^" / "**") _ b:factor_with_unary_op() { BinaryArithmetic::new(BinaryOp::Pow, a, b).into() }
+            / factor_with_postfix_op()
+
+        #[cache_left_rec]
+        pub rule factor_with_postfix_op() -> Expr
+            = a:factor_with_postfix_op() "!" end:position!() {
+                let span = Span::new(a.span().start, end);
+                UnaryArithmetic::new(UnaryOp::Fac, a).with_span(span).into()
+            }
             / factor()
 
         #[cache]
@@ -31,21 +80,25 @@ peg::parser! {
             = number()
             / function_call()
             / identifier()
-            / "(" _ e:expr() _ ")" { e }
+            / start:position!() "(" _ e:expr() _ ")" end:position!() { e.with_span(Span::new(start, end)) }
 
         pub rule function_call() -> Expr
-            = id:identifier() _ v:bracketed(<commasep(<expr()>)>) {
-                FunctionCall::new (
-                    if let Expr::Atom(Atom::Ident(x)) = id { x } else { "".to_owned() },
-                    v
-                ).into()
+            = start:position!() id:name() _ v:bracketed(<commasep(<expr()>)>) end:position!() {
+                FunctionCall::new(id, v).with_span(Span::new(start, end)).into()
             }
 
         pub rule number() -> Expr
-            = n:$("-"? ("0" / ['1'..='9']['0'..='9']*) ("." ['0'..='9']+)?) { Atom::Number(n.parse::<f64>().unwrap()).into() }
+            = start:position!() n:$("-"? ("0" / ['1'..='9']['0'..='9']*) ("." ['0'..='9']+)?) end:position!() {
+                Expr::Atom(Atom::Number(n.parse::<f64>().unwrap()), Span::new(start, end))
+            }
 
         pub rule identifier() -> Expr
-            = id:$(['a'..='z' | 'A'..='Z' | '_'] ['a'..='z' | 'A'..='Z' | '0'..='9' | '_']*) { Atom::Ident(id.to_owned()).into() }
+            = start:position!() id:name() end:position!() { Expr::Atom(Atom::Ident(id), Span::new(start, end)) }
+
+        rule name() -> String
+            = id:$(['a'..='z' | 'A'..='Z' | '_'] ident_char()*) { id.to_owned() }
+
+        rule ident_char() = ['a'..='z' | 'A'..='Z' | '0'..='9' | '_']
 
         rule commasep<T>(x: rule<T>) -> Vec<T> = v:(x() ** ( _ "," _ ) ) ","? { v }
         rule bracketed<T>(x: rule<T>) -> T = "(" _  v:x() _ ")" { v }
@@ -58,10 +111,163 @@ peg::parser! {
 mod tests {
     use super::*;
 
+    /// Resets every span, so that parsed trees compare equal to trees built by
+    /// hand. Spans are checked on their own in `spans`.
+    trait WithoutSpans {
+        fn without_spans(self) -> Self;
+    }
+
+    impl WithoutSpans for Expr {
+        fn without_spans(self) -> Self {
+            match self {
+                Expr::BinaryArithmetic(b) => {
+                    let b = *b;
+                    BinaryArithmetic::new(b.op, b.lhs.without_spans(), b.rhs.without_spans()).into()
+                }
+                Expr::UnaryArithmetic(u) => {
+                    let u = *u;
+                    UnaryArithmetic::new(u.op, u.value.without_spans()).into()
+                }
+                Expr::Atom(a, _) => a.into(),
+                Expr::FunctionCall(f) => FunctionCall::new(f.name, f.args.without_spans()).into(),
+            }
+        }
+    }
+
+    impl WithoutSpans for Stmt {
+        fn without_spans(self) -> Self {
+            match self {
+                Stmt::Let(a) => Stmt::Let(Assignment::new(a.name, a.value.without_spans())),
+                Stmt::Assign(a) => Stmt::Assign(Assignment::new(a.name, a.value.without_spans())),
+                Stmt::Function(def) => Stmt::Function(FunctionDef::new(
+                    def.name,
+                    def.params,
+                    def.body.without_spans(),
+                )),
+                Stmt::Expr(e) => Stmt::Expr(e.without_spans()),
+            }
+        }
+    }
+
+    impl<T: WithoutSpans> WithoutSpans for Vec<T> {
+        fn without_spans(self) -> Self {
+            self.into_iter().map(WithoutSpans::without_spans).collect()
+        }
+    }
+
+    impl<T: WithoutSpans, E> WithoutSpans for Result<T, E> {
+        fn without_spans(self) -> Self {
+            self.map(WithoutSpans::without_spans)
+        }
+    }
+
+    #[test]
+    fn program() {
+        let num = |n: f64| -> Expr { Atom::Number(n).into() };
+        let ident = |id: &str| -> Expr { Atom::Ident(id.into()).into() };
+        assert_eq!(
+            calc_parser::program(" 1 ").without_spans(),
+            Ok(vec![num(1.).into()])
+        );
+        assert_eq!(
+            calc_parser::program("let x = 1; x = x + 1\n\nx").without_spans(),
+            Ok(vec![
+                Stmt::Let(Assignment::new("x".into(), num(1.))),
+                Stmt::Assign(Assignment::new(
+                    "x".into(),
+                    BinaryArithmetic::new(BinaryOp::Add, ident("x"), num(1.)).into()
+                )),
+                ident("x").into(),
+            ])
+        );
+        assert_eq!(
+            calc_parser::program("a = 1;\r\nb = 2;;\n").without_spans(),
+            Ok(vec![
+                Stmt::Assign(Assignment::new("a".into(), num(1.))),
+                Stmt::Assign(Assignment::new("b".into(), num(2.))),
+            ])
+        );
+        assert_eq!(
+            calc_parser::program("letter = 1").without_spans(),
+            Ok(vec![Stmt::Assign(Assignment::new(
+                "letter".into(),
+                num(1.)
+            ))])
+        );
+        assert!(calc_parser::program("").is_err());
+        assert!(calc_parser::program("; 1").is_err());
+        assert!(calc_parser::program("1 = 2").is_err());
+        assert!(calc_parser::program("let 1 = 2").is_err());
+    }
+
+    #[test]
+    fn function_def() {
+        let ident = |id: &str| -> Expr { Atom::Ident(id.into()).into() };
+        assert_eq!(
+            calc_parser::statement("f(x, y) = x * y").without_spans(),
+            Ok(Stmt::Function(FunctionDef::new(
+                "f".into(),
+                vec!["x".into(), "y".into()],
+                BinaryArithmetic::new(BinaryOp::Mul, ident("x"), ident("y")).into()
+            )))
+        );
+        assert_eq!(
+            calc_parser::statement("one() = 1").without_spans(),
+            Ok(Stmt::Function(FunctionDef::new(
+                "one".into(),
+                vec![],
+                Atom::Number(1.).into()
+            )))
+        );
+        assert_eq!(
+            calc_parser::statement("f(x)").without_spans(),
+            Ok(Stmt::Expr(
+                FunctionCall::new("f".into(), vec![ident("x")]).into()
+            ))
+        );
+        assert!(calc_parser::statement("f(x, x) = x").is_err());
+        assert!(calc_parser::statement("f(1) = 1").is_err());
+    }
+
+    #[test]
+    fn spans() {
+        let span_of = |e: &Expr| (e.span().start, e.span().end);
+        let e = calc_parser::expr("-(a + 2) * f(3!)").unwrap();
+        assert_eq!(span_of(&e), (0, 16));
+        let Expr::BinaryArithmetic(ref mul) = e else {
+            panic!("expected a binary expression");
+        };
+        assert_eq!(span_of(&mul.lhs), (0, 8));
+        assert_eq!(span_of(&mul.rhs), (11, 16));
+        let Expr::UnaryArithmetic(ref neg) = mul.lhs else {
+            panic!("expected a unary expression");
+        };
+        assert_eq!(span_of(&neg.value), (1, 8));
+        let Expr::BinaryArithmetic(ref add) = neg.value else {
+            panic!("expected a binary expression");
+        };
+        assert_eq!(span_of(&add.lhs), (2, 3));
+        assert_eq!(span_of(&add.rhs), (6, 7));
+        let Expr::FunctionCall(ref call) = mul.rhs else {
+            panic!("expected a function call");
+        };
+        assert_eq!(span_of(&call.args[0]), (13, 15));
+
+        let program = calc_parser::program("let x = 1\n f(y) = y").unwrap();
+        let Stmt::Let(ref a) = program[0] else {
+            panic!("expected a let statement");
+        };
+        assert_eq!((a.span.start, a.span.end), (0, 9));
+        let Stmt::Function(ref def) = program[1] else {
+            panic!("expected a function definition");
+        };
+        assert_eq!((def.span.start, def.span.end), (11, 19));
+    }
+
     #[test]
     fn expr() {
         assert_eq!(
-            calc_parser::expr("1 + 9 / 10"),
+            calc_parser::expr("1 + 9 / 10").without_spans(),
             Ok(BinaryArithmetic::new(
                 BinaryOp::Add,
                 Atom::Number(1_f64).into(),
@@ -75,7 +281,7 @@ mod tests {
             .into())
         );
         assert_eq!(
-            calc_parser::expr("(1 + 9) / 10"),
+            calc_parser::expr("(1 + 9) / 10").without_spans(),
             Ok(BinaryArithmetic::new(
                 BinaryOp::Div,
                 BinaryArithmetic::new(
@@ -93,7 +299,7 @@ mod tests {
     #[test]
     fn term() {
         assert_eq!(
-            calc_parser::term("1 * 9 / 10"),
+            calc_parser::term("1 * 9 / 10").without_spans(),
             Ok(BinaryArithmetic::new(
                 BinaryOp::Div,
                 BinaryArithmetic::new(
@@ -107,7 +313,7 @@ mod tests {
             .into())
         );
         assert_eq!(
-            calc_parser::term("a * 9"),
+            calc_parser::term("a * 9").without_spans(),
             Ok(BinaryArithmetic::new(
                 BinaryOp::Mul,
                 Atom::Ident("a".into()).into(),
@@ -116,7 +322,7 @@ mod tests {
             .into())
         );
         assert_eq!(
-            calc_parser::term("9 * a"),
+            calc_parser::term("9 * a").without_spans(),
             Ok(BinaryArithmetic::new(
                 BinaryOp::Mul,
                 Atom::Number(9_f64).into(),
@@ -126,10 +332,48 @@ mod tests {
         );
     }
 
+    #[test]
+    fn term_modulo() {
+        let num = |n: f64| -> Expr { Atom::Number(n).into() };
+        for (input, op) in [
+            ("7 % 3", BinaryOp::Mod),
+            ("7 // 3", BinaryOp::FloorDiv),
+            ("7 rem 3", BinaryOp::Rem),
+        ] {
+            assert_eq!(
+                calc_parser::term(input).without_spans(),
+                Ok(BinaryArithmetic::new(op, num(7.), num(3.)).into())
+            );
+        }
+        assert_eq!(
+            calc_parser::term("7 // 3 * 2").without_spans(),
+            Ok(BinaryArithmetic::new(
+                BinaryOp::Mul,
+                BinaryArithmetic::new(BinaryOp::FloorDiv, num(7.), num(3.)).into(),
+                num(2.),
+            )
+            .into())
+        );
+        assert_eq!(
+            calc_parser::term("a rem b").without_spans(),
+            Ok(BinaryArithmetic::new(
+                BinaryOp::Rem,
+                Atom::Ident("a".into()).into(),
+                Atom::Ident("b".into()).into(),
+            )
+            .into())
+        );
+        assert_eq!(
+            calc_parser::term("rem").without_spans(),
+            Ok(Atom::Ident("rem".into()).into())
+        );
+        assert!(calc_parser::term("7 remainder").is_err());
+    }
+
     #[test]
     fn factor_with_unary_op() {
         assert_eq!(
-            calc_parser::factor_with_unary_op("-f(-x)"),
+            calc_parser::factor_with_unary_op("-f(-x)").without_spans(),
             Ok(UnaryArithmetic::new(
                 UnaryOp::Neg,
                 FunctionCall::new(
@@ -141,7 +385,7 @@ mod tests {
             .into())
         );
         assert_eq!(
-            calc_parser::factor_with_unary_op("f(x!)!"),
+            calc_parser::factor_with_unary_op("f(x!)!").without_spans(),
             Ok(UnaryArithmetic::new(
                 UnaryOp::Fac,
                 FunctionCall::new(
@@ -154,16 +398,64 @@ mod tests {
         );
     }
 
+    #[test]
+    fn power() {
+        let pow = |a: Expr, b: Expr| -> Expr { BinaryArithmetic::new(BinaryOp::Pow, a, b).into() };
+        let num = |n: f64| -> Expr { Atom::Number(n).into() };
+        assert_eq!(
+            calc_parser::expr("2^3").without_spans(),
+            Ok(pow(num(2.), num(3.)))
+        );
+        assert_eq!(
+            calc_parser::expr("2 ** 3").without_spans(),
+            Ok(pow(num(2.), num(3.)))
+        );
+        assert_eq!(
+            calc_parser::expr("2^3^2").without_spans(),
+            Ok(pow(num(2.), pow(num(3.), num(2.))))
+        );
+        assert_eq!(
+            calc_parser::expr("-2^2").without_spans(),
+            Ok(UnaryArithmetic::new(UnaryOp::Neg, pow(num(2.), num(2.))).into())
+        );
+        assert_eq!(
+            calc_parser::expr("2^-1").without_spans(),
+            Ok(pow(
+                num(2.),
+                UnaryArithmetic::new(UnaryOp::Neg, num(1.)).into()
+            ))
+        );
+        assert_eq!(
+            calc_parser::expr("3!^2").without_spans(),
+            Ok(pow(
+                UnaryArithmetic::new(UnaryOp::Fac, num(3.)).into(),
+                num(2.)
+            ))
+        );
+        assert_eq!(
+            calc_parser::expr("2 * 3^2").without_spans(),
+            Ok(BinaryArithmetic::new(BinaryOp::Mul, num(2.), pow(num(3.), num(2.))).into())
+        );
+        assert!(calc_parser::expr("2^").is_err());
+        assert!(calc_parser::expr("2***3").is_err());
+    }
+
     #[test]
     fn factor() {
         assert_eq!(
-            calc_parser::factor("f(x)"),
+            calc_parser::factor("f(x)").without_spans(),
             Ok(FunctionCall::new("f".into(), vec![Atom::Ident("x".into()).into()]).into())
         );
-        assert_eq!(calc_parser::factor("0"), Ok(Atom::Number(0 as f64).into()));
-        assert_eq!(calc_parser::factor("o"), Ok(Atom::Ident("o".into()).into()));
         assert_eq!(
-            calc_parser::factor("(1+2)"),
+            calc_parser::factor("0").without_spans(),
+            Ok(Atom::Number(0 as f64).into())
+        );
+        assert_eq!(
+            calc_parser::factor("o").without_spans(),
+            Ok(Atom::Ident("o".into()).into())
+        );
+        assert_eq!(
+            calc_parser::factor("(1+2)").without_spans(),
             Ok(BinaryArithmetic::new(
                 BinaryOp::Add,
                 Atom::Number(1_f64).into(),
@@ -176,34 +468,52 @@ mod tests {
     #[test]
     fn function_call() {
         assert_eq!(
-            calc_parser::function_call("f(x)"),
+            calc_parser::function_call("f(x)").without_spans(),
             Ok(FunctionCall::new("f".into(), vec![Atom::Ident("x".into()).into()]).into())
         );
         assert_eq!(
-            calc_parser::function_call("f ( x )"),
+            calc_parser::function_call("f ( x )").without_spans(),
             Ok(FunctionCall::new("f".into(), vec![Atom::Ident("x".into()).into()]).into())
         );
         assert_eq!(
-            calc_parser::function_call("log(2, 4)"),
+            calc_parser::function_call("log(2, 4)").without_spans(),
             Ok(FunctionCall::new(
                 "log".into(),
                 vec![Atom::Number(2_f64).into(), Atom::Number(4_f64).into()]
             )
             .into())
         );
+        let program = calc_parser::program("1;  f( g(x) )").unwrap();
+        let Stmt::Expr(Expr::FunctionCall(ref call)) = program[1] else {
+            panic!("expected a function call");
+        };
+        assert_eq!((call.span.start, call.span.end), (4, 13));
+        let Expr::FunctionCall(ref inner) = call.args[0] else {
+            panic!("expected a function call");
+        };
+        assert_eq!((inner.span.start, inner.span.end), (7, 11));
         assert!(calc_parser::function_call("f").is_err());
         assert!(calc_parser::function_call("f(").is_err());
     }
 
     #[test]
     fn number() {
-        assert_eq!(calc_parser::number("0"), Ok(Atom::Number(0 as f64).into()));
-        assert_eq!(calc_parser::number("0.1"), Ok(Atom::Number(0.1_f64).into()));
         assert_eq!(
-            calc_parser::number("2.333"),
+            calc_parser::number("0").without_spans(),
+            Ok(Atom::Number(0 as f64).into())
+        );
+        assert_eq!(
+            calc_parser::number("0.1").without_spans(),
+            Ok(Atom::Number(0.1_f64).into())
+        );
+        assert_eq!(
+            calc_parser::number("2.333").without_spans(),
             Ok(Atom::Number(2.333_f64).into())
         );
-        assert_eq!(calc_parser::number("42"), Ok(Atom::Number(42_f64).into()));
+        assert_eq!(
+            calc_parser::number("42").without_spans(),
+            Ok(Atom::Number(42_f64).into())
+        );
         assert!(calc_parser::number("00").is_err());
         assert!(calc_parser::number("o").is_err());
     }
@@ -211,19 +521,19 @@ mod tests {
     #[test]
     fn identifier() {
         assert_eq!(
-            calc_parser::identifier("o"),
+            calc_parser::identifier("o").without_spans(),
             Ok(Atom::Ident("o".into()).into())
         );
         assert_eq!(
-            calc_parser::identifier("_parser"),
+            calc_parser::identifier("_parser").without_spans(),
             Ok(Atom::Ident("_parser".into()).into())
         );
         assert_eq!(
-            calc_parser::identifier("par_123"),
+            calc_parser::identifier("par_123").without_spans(),
             Ok(Atom::Ident("par_123".into()).into())
         );
         assert_eq!(
-            calc_parser::identifier("_123"),
+            calc_parser::identifier("_123").without_spans(),
             Ok(Atom::Ident("_123".into()).into())
         );
         assert!(calc_parser::identifier("123").is_err());
@@ -0,0 +1,276 @@
+use super::COMMANDS;
+use rcalc::Arity;
+use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::{CmdKind, Highlighter, MatchingBracketHighlighter};
+use rustyline::hint::{Hint, Hinter};
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+use std::borrow::Cow;
+
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// A name of the session, as offered for completion.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub doc: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SymbolKind {
+    Variable,
+    Function(Arity),
+}
+
+/// Completion, inline hints and bracket matching for the line editor. It
+/// works on a snapshot of the session symbols, refreshed after every line.
+#[derive(Default)]
+pub struct ReplHelper {
+    /// Sorted by name, so that a name comes before the longer ones it prefixes
+    symbols: Vec<Symbol>,
+    brackets: MatchingBracketHighlighter,
+    color: bool,
+}
+
+impl ReplHelper {
+    pub fn new(color: bool) -> Self {
+        ReplHelper {
+            color,
+            ..Default::default()
+        }
+    }
+
+    pub fn set_symbols(&mut self, mut symbols: Vec<Symbol>) {
+        symbols.sort_by(|a, b| a.name.cmp(&b.name));
+        self.symbols = symbols;
+    }
+
+    fn starting_with<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a Symbol> + 'a {
+        self.symbols
+            .iter()
+            .filter(move |symbol| symbol.name.starts_with(prefix))
+    }
+}
+
+/// Start of the identifier that ends at `pos`, `pos` itself if there is none.
+fn word_start(line: &str, pos: usize) -> usize {
+    let start = line[..pos]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| c.is_ascii_alphanumeric() || *c == '_')
+        .last()
+        .map_or(pos, |(i, _)| i);
+    // Identifiers cannot start with a digit, `2e` is no prefix of `exp`
+    match line[start..pos].chars().next() {
+        Some(c) if c.is_ascii_digit() => pos,
+        _ => start,
+    }
+}
+
+/// Whether the word at `start` is the name of a meta-command.
+fn is_command(line: &str, start: usize) -> bool {
+    start == 1 && line.starts_with(':')
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let start = word_start(line, pos);
+        let prefix = &line[start..pos];
+        if is_command(line, start) {
+            let candidates = COMMANDS
+                .iter()
+                .filter(|command| command.starts_with(prefix))
+                .map(|command| Pair {
+                    display: command.to_string(),
+                    replacement: command.to_string(),
+                })
+                .collect();
+            return Ok((start, candidates));
+        }
+        if prefix.is_empty() {
+            return Ok((pos, Vec::new()));
+        }
+        let call = !line[pos..].starts_with('(');
+        let candidates = self
+            .starting_with(prefix)
+            .map(|symbol| Pair {
+                display: symbol.name.clone(),
+                replacement: match symbol.kind {
+                    SymbolKind::Function(_) if call => format!("{}(", symbol.name),
+                    _ => symbol.name.clone(),
+                },
+            })
+            .collect();
+        Ok((start, candidates))
+    }
+}
+
+/// The rest of a name followed by its description. Only the rest of the name
+/// is inserted when the hint is accepted.
+#[derive(Debug, PartialEq)]
+pub struct SymbolHint {
+    display: String,
+    completion: Option<String>,
+}
+
+impl Hint for SymbolHint {
+    fn display(&self) -> &str {
+        &self.display
+    }
+
+    fn completion(&self) -> Option<&str> {
+        self.completion.as_deref()
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = SymbolHint;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> Option<SymbolHint> {
+        if pos < line.len() {
+            return None;
+        }
+        let start = word_start(line, pos);
+        let prefix = &line[start..pos];
+        if prefix.is_empty() || is_command(line, start) {
+            return None;
+        }
+        let symbol = self.starting_with(prefix).next()?;
+        let rest = &symbol.name[prefix.len()..];
+        let description = match (&symbol.kind, &symbol.doc) {
+            (SymbolKind::Function(arity), Some(doc)) => format!("  ({arity}) {doc}"),
+            (SymbolKind::Function(arity), None) => format!("  ({arity})"),
+            (SymbolKind::Variable, Some(doc)) => format!("  {doc}"),
+            (SymbolKind::Variable, None) => String::new(),
+        };
+        if rest.is_empty() && description.is_empty() {
+            return None;
+        }
+        Some(SymbolHint {
+            display: format!("{rest}{description}"),
+            completion: (!rest.is_empty()).then(|| rest.to_string()),
+        })
+    }
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        if self.color {
+            self.brackets.highlight(line, pos)
+        } else {
+            Cow::Borrowed(line)
+        }
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        if self.color {
+            Cow::Owned(format!("{DIM}{hint}{RESET}"))
+        } else {
+            Cow::Borrowed(hint)
+        }
+    }
+
+    fn highlight_char(&self, line: &str, pos: usize, kind: CmdKind) -> bool {
+        self.color && self.brackets.highlight_char(line, pos, kind)
+    }
+}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustyline::history::DefaultHistory;
+
+    fn helper() -> ReplHelper {
+        let mut helper = ReplHelper::new(false);
+        helper.set_symbols(vec![
+            Symbol {
+                name: "sqrt".into(),
+                kind: SymbolKind::Function(Arity::Fixed(1)),
+                doc: Some("square root".into()),
+            },
+            Symbol {
+                name: "sum".into(),
+                kind: SymbolKind::Function(Arity::Variadic),
+                doc: None,
+            },
+            Symbol {
+                name: "PI".into(),
+                kind: SymbolKind::Variable,
+                doc: Some("half a turn".into()),
+            },
+            Symbol {
+                name: "principal".into(),
+                kind: SymbolKind::Variable,
+                doc: None,
+            },
+        ]);
+        helper
+    }
+
+    fn complete(line: &str) -> (usize, Vec<String>) {
+        let history = DefaultHistory::new();
+        let (start, candidates) = helper()
+            .complete(line, line.len(), &Context::new(&history))
+            .unwrap();
+        let replacements = candidates.into_iter().map(|pair| pair.replacement);
+        (start, replacements.collect())
+    }
+
+    fn hint(line: &str) -> Option<SymbolHint> {
+        let history = DefaultHistory::new();
+        helper().hint(line, line.len(), &Context::new(&history))
+    }
+
+    #[test]
+    fn completion() {
+        assert_eq!(complete("1 + s"), (4, vec!["sqrt(".into(), "sum(".into()]));
+        assert_eq!(complete("2*pr"), (2, vec!["principal".into()]));
+        assert_eq!(complete("P"), (0, vec!["PI".into()]));
+        assert_eq!(complete("1 + "), (4, vec![]));
+        assert_eq!(complete("2s"), (2, vec![]));
+        assert_eq!(complete(":v"), (1, vec!["vars".into()]));
+        assert_eq!(complete(":ast sq"), (5, vec!["sqrt(".into()]));
+
+        // No second bracket in front of an existing one
+        let history = DefaultHistory::new();
+        let (_, candidates) = helper()
+            .complete("sq(2)", 2, &Context::new(&history))
+            .unwrap();
+        assert_eq!(candidates[0].replacement, "sqrt");
+    }
+
+    #[test]
+    fn hints() {
+        assert_eq!(
+            hint("1 + sq"),
+            Some(SymbolHint {
+                display: "rt  (1 argument) square root".into(),
+                completion: Some("rt".into()),
+            })
+        );
+        assert_eq!(
+            hint("sum"),
+            Some(SymbolHint {
+                display: "  (any number of arguments)".into(),
+                completion: None,
+            })
+        );
+        assert_eq!(hint("P").unwrap().display, "I  half a turn");
+        assert_eq!(hint("principal"), None);
+        assert_eq!(hint("x"), None);
+        assert_eq!(hint(":s"), None);
+    }
+}
@@ -0,0 +1,711 @@
+mod helper;
+
+use crate::cli::NotationArg;
+use crate::Exit;
+use clap::ValueEnum;
+use helper::{ReplHelper, Symbol, SymbolKind};
+use rcalc::ast::{Assignment, Atom, Expr, Span, Stmt};
+use rcalc::diagnostics::did_you_mean;
+use rcalc::format::NumberFormat;
+use rcalc::visitor::eval::Function;
+#[cfg(feature = "jit")]
+use rcalc::visitor::CalculatorJIT;
+use rcalc::visitor::{PrettyPrinter, Visitor};
+#[cfg(feature = "jit")]
+use rcalc::EvalError;
+use rcalc::{Diagnostic, Engine, Error};
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::Editor;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+const PROMPT: &str = ">> ";
+const COMMANDS: &[&str] = &[
+    "help",
+    "quit",
+    "vars",
+    "funcs",
+    "ast",
+    "jit",
+    "precision",
+    "digits",
+    "format",
+    "strict",
+];
+const HELP: &str = "\
+Statements are evaluated as they are entered, `ans` holds the last result.
+
+:vars              list the variables
+:funcs             list the functions
+:ast <program>     print the syntax tree of a program
+:jit on|off        compile with the JIT backend
+:precision N|off   number of decimals to print
+:digits N|off      number of significant digits to print
+:format NOTATION   auto, fixed, sci or eng
+:strict on|off     treat division by zero as an error
+:help              show this message
+:quit              leave, as does Ctrl-D";
+
+/// Options of a session, taken from the command line options of the same
+/// name and changed by meta-commands.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub jit: bool,
+    pub format: NumberFormat,
+    pub strict: bool,
+    pub color: bool,
+}
+
+/// What a line of input gave.
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    Output(String),
+    Error(String),
+    Quit,
+}
+
+/// State of an interactive session. The interpreter always runs first, so
+/// that errors are reported the same way whatever the backend, and the JIT
+/// session is kept in step by replaying every program into it. Programs the
+/// JIT cannot compile are refused while it is on.
+pub struct Session {
+    engine: Engine,
+    settings: Settings,
+    /// Line that defined each user function, errors raised in its body are
+    /// shown against it
+    sources: HashMap<String, String>,
+    /// Every program run so far, `ans` updates included
+    #[cfg(feature = "jit")]
+    programs: Vec<Vec<Stmt>>,
+    #[cfg(feature = "jit")]
+    jit: Option<CalculatorJIT<'static>>,
+}
+
+impl Session {
+    pub fn new(settings: Settings, variables: Vec<(String, f64)>) -> Result<Self, String> {
+        let jit = settings.jit;
+        let mut session = Session {
+            engine: Engine::new(),
+            settings: Settings {
+                jit: false,
+                ..settings
+            },
+            sources: HashMap::new(),
+            #[cfg(feature = "jit")]
+            programs: Vec::new(),
+            #[cfg(feature = "jit")]
+            jit: None,
+        };
+        session.engine.set_strict(session.settings.strict);
+        for (name, value) in variables {
+            let program = vec![Stmt::Let(Assignment::new(name, number(value)))];
+            if let Err(e) = session.engine.run(&program) {
+                return Err(session.render("", &e, None));
+            }
+            session.record(program)?;
+        }
+        session.set_jit(jit)?;
+        Ok(session)
+    }
+
+    pub fn handle(&mut self, line: &str) -> Reply {
+        let line = line.trim();
+        if line.is_empty() {
+            return Reply::Output(String::new());
+        }
+        match line.strip_prefix(':') {
+            Some(command) => self.command(command.trim()),
+            None => self.eval(line),
+        }
+    }
+
+    fn eval(&mut self, input: &str) -> Reply {
+        // A program runs on a copy of the session, so that one failing halfway
+        // leaves none of its statements behind: they would never reach the JIT
+        let mut engine = self.engine.clone();
+        let value = match rcalc::parse(input).and_then(|program| {
+            self.check_jit(&program)?;
+            let value = engine.run(&program)?;
+            Ok((value, program))
+        }) {
+            Ok((value, program)) => {
+                self.engine = engine;
+                for stmt in &program {
+                    if let Stmt::Function(def) = stmt {
+                        self.sources.insert(def.name.clone(), input.into());
+                    }
+                }
+                let compiled = match self.record(program) {
+                    Ok(compiled) => compiled,
+                    Err(e) => return Reply::Error(e),
+                };
+                // Function definitions alone have no value, whatever the JIT returns
+                value.map(|value| compiled.unwrap_or(value))
+            }
+            Err(e) => {
+                let failed_function = engine.calculator().failed_function();
+                return Reply::Error(self.render(input, &e, failed_function));
+            }
+        };
+        let Some(value) = value else {
+            return Reply::Output(String::new());
+        };
+
+        let ans = vec![Stmt::Assign(Assignment::new("ans".into(), number(value)))];
+        self.engine
+            .run(&ans)
+            .expect("Assigning a number cannot fail");
+        self.record(ans).expect("Assigning a number cannot fail");
+        Reply::Output(self.settings.format.format(value))
+    }
+
+    /// Every variable and function visible in the session.
+    fn symbols(&self) -> Vec<Symbol> {
+        let calculator = self.engine.calculator();
+        let variables = calculator.variables().map(|(name, _)| Symbol {
+            name: name.into(),
+            kind: SymbolKind::Variable,
+            doc: calculator.variable_doc(name).map(Into::into),
+        });
+        let functions = calculator.functions().map(|(name, func)| Symbol {
+            name: name.into(),
+            kind: SymbolKind::Function(func.arity()),
+            doc: calculator.function_doc(name).map(Into::into),
+        });
+        variables.chain(functions).collect()
+    }
+
+    fn command(&mut self, command: &str) -> Reply {
+        let (name, arg) = match command.split_once(char::is_whitespace) {
+            Some((name, arg)) => (name, arg.trim()),
+            None => (command, ""),
+        };
+        match (name, arg) {
+            ("help" | "h", _) => Reply::Output(HELP.into()),
+            ("quit" | "q" | "exit", _) => Reply::Quit,
+            ("vars", _) => {
+                let mut vars: Vec<_> = self.engine.calculator().variables().collect();
+                vars.sort_by(|a, b| a.0.cmp(b.0));
+                let lines: Vec<_> = vars
+                    .into_iter()
+                    .map(|(name, value)| format!("{name} = {}", self.settings.format.format(value)))
+                    .collect();
+                Reply::Output(lines.join("\n"))
+            }
+            ("funcs", _) => {
+                let mut funcs: Vec<_> = self.engine.calculator().functions().collect();
+                funcs.sort_by(|a, b| a.0.cmp(b.0));
+                let lines: Vec<_> = funcs
+                    .into_iter()
+                    .map(|(name, func)| match func {
+                        Function::Native(arity, _) => format!("{name}: takes {arity}"),
+                        Function::User(def) => format!("{name}({})", def.params.join(", ")),
+                    })
+                    .collect();
+                Reply::Output(lines.join("\n"))
+            }
+            ("ast", "") => Reply::Error("error: `:ast` needs a program\n".into()),
+            ("ast", program) => match rcalc::parse(program) {
+                Ok(program) => {
+                    let mut printer = PrettyPrinter::new(2);
+                    for stmt in &program {
+                        printer.visit_stmt(stmt);
+                    }
+                    Reply::Output(printer.finish().trim_end().into())
+                }
+                Err(e) => Reply::Error(self.render(program, &e, None)),
+            },
+            ("jit", "") => Reply::Output(on_off(self.settings.jit).into()),
+            ("jit", arg) => match parse_on_off(arg).map(|on| self.set_jit(on)) {
+                Some(Ok(())) => Reply::Output(String::new()),
+                Some(Err(e)) => Reply::Error(e),
+                None => usage("jit on|off"),
+            },
+            ("precision", "") => Reply::Output(
+                self.settings
+                    .format
+                    .precision
+                    .map_or("off".into(), |precision| precision.to_string()),
+            ),
+            ("precision", "off") => {
+                self.settings.format.precision = None;
+                Reply::Output(String::new())
+            }
+            // Decimals and significant digits exclude each other, as on the
+            // command line
+            ("precision", arg) => match arg.parse() {
+                Ok(precision) => {
+                    self.settings.format.precision = Some(precision);
+                    self.settings.format.sig_digits = None;
+                    Reply::Output(String::new())
+                }
+                Err(_) => usage("precision N|off"),
+            },
+            ("digits", "") => Reply::Output(
+                self.settings
+                    .format
+                    .sig_digits
+                    .map_or("off".into(), |digits| digits.to_string()),
+            ),
+            ("digits", "off") => {
+                self.settings.format.sig_digits = None;
+                Reply::Output(String::new())
+            }
+            ("digits", arg) => match arg.parse() {
+                Ok(digits) if digits > 0 => {
+                    self.settings.format.sig_digits = Some(digits);
+                    self.settings.format.precision = None;
+                    Reply::Output(String::new())
+                }
+                _ => usage("digits N|off"),
+            },
+            ("format", "") => {
+                let notation = NotationArg::from(self.settings.format.notation);
+                Reply::Output(setting_name(notation))
+            }
+            ("format", arg) => match NotationArg::from_str(arg, false) {
+                Ok(notation) => {
+                    self.settings.format.notation = notation.into();
+                    Reply::Output(String::new())
+                }
+                Err(_) => {
+                    let names: Vec<_> = NotationArg::value_variants()
+                        .iter()
+                        .copied()
+                        .map(setting_name)
+                        .collect();
+                    usage(&format!("format {}", names.join("|")))
+                }
+            },
+            ("strict", "") => Reply::Output(on_off(self.settings.strict).into()),
+            ("strict", arg) => match parse_on_off(arg) {
+                Some(strict) => {
+                    self.settings.strict = strict;
+                    self.engine.set_strict(strict);
+                    Reply::Output(String::new())
+                }
+                None => usage("strict on|off"),
+            },
+            _ => {
+                let mut message = format!("unknown command `:{name}`");
+                if let Some(suggestion) = did_you_mean(name, COMMANDS.iter().copied()) {
+                    message += &format!(", did you mean `:{suggestion}`?");
+                }
+                let diagnostic = Diagnostic::new(message).with_help("`:help` lists the commands");
+                Reply::Error(diagnostic.render("", self.settings.color))
+            }
+        }
+    }
+
+    /// `failed_function` is the function whose body raised `error`, if any.
+    fn render(&self, input: &str, error: &Error, failed_function: Option<&str>) -> String {
+        let mut diagnostics = self.engine.diagnostics(error, input);
+        let mut source = input;
+        // An error raised in the body of a function points into the line that
+        // defined the function, not into `input`
+        if let (Error::Eval(_), Some(name)) = (error, failed_function) {
+            match self.sources.get(name) {
+                Some(definition) => source = definition,
+                None => diagnostics
+                    .iter_mut()
+                    .for_each(|diagnostic| diagnostic.span = None),
+            }
+        }
+        diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.render(source, self.settings.color))
+            .collect()
+    }
+
+    /// Reports what would stop the JIT, when it is on, from compiling
+    /// `program`, before the interpreter runs it.
+    #[cfg(feature = "jit")]
+    fn check_jit(&self, program: &[Stmt]) -> Result<(), Error> {
+        match &self.jit {
+            Some(jit) => jit.check_program(program).map_err(Error::Check),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(not(feature = "jit"))]
+    fn check_jit(&self, _program: &[Stmt]) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Keeps `program` for the JIT session and returns the value the JIT
+    /// computes for it, if enabled. The JIT is switched off if it cannot
+    /// compile the program.
+    #[cfg(feature = "jit")]
+    fn record(&mut self, program: Vec<Stmt>) -> Result<Option<f64>, String> {
+        let value = match self.jit.as_mut().map(|jit| run_jit(jit, &program)) {
+            Some(Ok(value)) => Some(value),
+            Some(Err(errors)) => {
+                self.jit = None;
+                self.settings.jit = false;
+                return Err(self.render_jit_errors(&errors, "the JIT backend is off now"));
+            }
+            None => None,
+        };
+        self.programs.push(program);
+        Ok(value)
+    }
+
+    #[cfg(not(feature = "jit"))]
+    fn record(&mut self, _program: Vec<Stmt>) -> Result<Option<f64>, String> {
+        Ok(None)
+    }
+
+    /// The errors of a program compiled by the JIT, followed by `message`.
+    /// Their spans are left out, the program may come from a line long gone.
+    #[cfg(feature = "jit")]
+    fn render_jit_errors(&self, errors: &[EvalError], message: &str) -> String {
+        errors
+            .iter()
+            .map(|e| Diagnostic::new(e.to_string()))
+            .chain([Diagnostic::new(message)])
+            .map(|diagnostic| diagnostic.render("", self.settings.color))
+            .collect()
+    }
+
+    #[cfg(feature = "jit")]
+    fn set_jit(&mut self, on: bool) -> Result<(), String> {
+        if on && self.jit.is_none() {
+            let mut jit = CalculatorJIT::new(crate::jit_context());
+            jit.preset().unwrap();
+            for program in &self.programs {
+                if let Err(errors) = run_jit(&mut jit, program) {
+                    let message = "the JIT backend cannot compile the session, it stays off";
+                    return Err(self.render_jit_errors(&errors, message));
+                }
+            }
+            self.jit = Some(jit);
+        } else if !on {
+            self.jit = None;
+        }
+        self.settings.jit = on;
+        Ok(())
+    }
+
+    #[cfg(not(feature = "jit"))]
+    fn set_jit(&mut self, on: bool) -> Result<(), String> {
+        if on {
+            let diagnostic = Diagnostic::new("rcalc was built without feature `jit`");
+            return Err(diagnostic.render("", self.settings.color));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "jit")]
+fn run_jit(jit: &mut CalculatorJIT<'static>, program: &[Stmt]) -> Result<f64, Vec<EvalError>> {
+    let calc_main = jit.compile_program(program)?;
+    Ok(unsafe { calc_main.call() })
+}
+
+fn number(value: f64) -> Expr {
+    Expr::Atom(Atom::Number(value), Span::default())
+}
+
+/// Name of a setting as the command line spells it.
+fn setting_name(value: impl ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .expect("Settings have names")
+        .get_name()
+        .to_string()
+}
+
+fn parse_on_off(arg: &str) -> Option<bool> {
+    match arg {
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => None,
+    }
+}
+
+fn on_off(on: bool) -> &'static str {
+    if on {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+fn usage(usage: &str) -> Reply {
+    Reply::Error(format!("usage: :{usage}\n"))
+}
+
+/// History file in the XDG data directory, `~/.local/share/rcalc/history`
+/// by default.
+fn history_path() -> Option<PathBuf> {
+    Some(dirs::data_dir()?.join("rcalc").join("history"))
+}
+
+/// Reads lines until end of input or `:quit`.
+pub fn run(settings: Settings, variables: Vec<(String, f64)>) -> ExitCode {
+    let mut session = match Session::new(settings, variables) {
+        Ok(session) => session,
+        // Variables are checked before, only the JIT backend can refuse them
+        Err(e) => {
+            eprint!("{e}");
+            return Exit::Backend.into();
+        }
+    };
+    let color = session.settings.color;
+    let mut editor: Editor<ReplHelper, DefaultHistory> = match Editor::new() {
+        Ok(editor) => editor,
+        // A terminal that cannot be used counts as an unreadable input
+        Err(e) => {
+            eprintln!("error: {e}");
+            return Exit::Usage.into();
+        }
+    };
+    let mut helper = ReplHelper::new(color);
+    helper.set_symbols(session.symbols());
+    editor.set_helper(Some(helper));
+    let history = history_path();
+    if let Some(path) = &history {
+        // There is no history on the first run
+        let _ = editor.load_history(path);
+    }
+
+    loop {
+        match editor.readline(PROMPT) {
+            Ok(line) => {
+                if !line.trim().is_empty() {
+                    let _ = editor.add_history_entry(line.as_str());
+                }
+                match session.handle(&line) {
+                    Reply::Output(output) if output.is_empty() => {}
+                    Reply::Output(output) => println!("{output}"),
+                    Reply::Error(error) => eprint!("{error}"),
+                    Reply::Quit => break,
+                }
+                if let Some(helper) = editor.helper_mut() {
+                    helper.set_symbols(session.symbols());
+                }
+            }
+            // Ctrl-C drops the line being edited
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("error: {e}");
+                return Exit::Usage.into();
+            }
+        }
+    }
+
+    if let Some(path) = &history {
+        let saved = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .map_err(|e| e.to_string())
+            .and_then(|()| editor.save_history(path).map_err(|e| e.to_string()));
+        if let Err(e) = saved {
+            eprintln!("warning: failed to save the history: {e}");
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> Session {
+        let settings = Settings {
+            jit: false,
+            format: NumberFormat::default(),
+            strict: false,
+            color: false,
+        };
+        Session::new(settings, vec![("rate".into(), 0.5)]).unwrap()
+    }
+
+    fn output(text: &str) -> Reply {
+        Reply::Output(text.into())
+    }
+
+    #[test]
+    fn state() {
+        let mut session = session();
+        assert_eq!(session.handle("1 + 2"), output("3"));
+        assert_eq!(session.handle("ans * 2"), output("6"));
+        assert_eq!(session.handle("let x = ans + rate"), output("6.5"));
+        assert_eq!(session.handle("f(y) = x * y"), output(""));
+        assert_eq!(session.handle("f(2)"), output("13"));
+        assert_eq!(session.handle("   "), output(""));
+
+        let Reply::Error(error) = session.handle("f(1) + g(2)") else {
+            panic!("Expected an error");
+        };
+        assert!(error.starts_with("error: unknown function `g`"));
+        // A failed line leaves `ans` alone
+        assert_eq!(session.handle("ans"), output("13"));
+        let Reply::Error(error) = session.handle("1 +") else {
+            panic!("Expected an error");
+        };
+        assert!(error.contains("1 | 1 +"));
+    }
+
+    #[test]
+    fn failed_programs() {
+        let mut session = session();
+        assert!(matches!(session.handle("x = 5; (-1)!"), Reply::Error(_)));
+        // Nothing of a failed program is kept, the JIT would never see it
+        let Reply::Error(error) = session.handle("x") else {
+            panic!("Expected an error");
+        };
+        assert!(error.starts_with("error: unknown variable `x`"), "{error}");
+        assert!(matches!(
+            session.handle("f(y) = y; 1 / sqrt(1, 2)"),
+            Reply::Error(_)
+        ));
+        assert!(matches!(session.handle("f(1)"), Reply::Error(_)));
+        assert_eq!(session.handle("let x = 1"), output("1"));
+    }
+
+    #[test]
+    fn function_errors() {
+        let mut session = session();
+        session.handle("f(x) = x + qqqqqqqqqqqqq");
+        session.handle("2");
+        // Shown against the definition, not against the call
+        let Reply::Error(error) = session.handle("f(1)") else {
+            panic!("Expected an error");
+        };
+        assert!(error.contains(" --> 1:12\n"), "{error}");
+        assert!(error.contains("1 | f(x) = x + qqqqqqqqqqqqq"), "{error}");
+
+        session.handle("g(x) = x / 0");
+        session.handle(":strict on");
+        let Reply::Error(error) = session.handle("1 + g(1)") else {
+            panic!("Expected an error");
+        };
+        assert!(error.contains("1 | g(x) = x / 0"), "{error}");
+        // Errors of the line itself still point into it
+        let Reply::Error(error) = session.handle("qqq + g(1)") else {
+            panic!("Expected an error");
+        };
+        assert!(error.contains("1 | qqq + g(1)"), "{error}");
+    }
+
+    #[test]
+    fn symbols() {
+        let mut session = session();
+        session.handle("f(a, b) = a + b");
+        let symbols = session.symbols();
+        let find = |name: &str| symbols.iter().find(|symbol| symbol.name == name).unwrap();
+        assert_eq!(find("f").kind, SymbolKind::Function(rcalc::Arity::Fixed(2)));
+        assert_eq!(find("f").doc, None);
+        assert_eq!(find("sqrt").doc.as_deref(), Some("square root"));
+        assert_eq!(find("rate").kind, SymbolKind::Variable);
+        assert!(find("PI").doc.is_some());
+    }
+
+    #[test]
+    fn listings() {
+        let mut session = session();
+        session.handle("let x = 2");
+        session.handle("f(a, b) = a + b");
+        let Reply::Output(vars) = session.handle(":vars") else {
+            panic!("Expected the variables");
+        };
+        assert!(vars.contains("ans = 2\n"));
+        assert!(vars.contains("rate = 0.5\n"));
+        assert!(vars.ends_with("x = 2"));
+        let Reply::Output(funcs) = session.handle(":funcs") else {
+            panic!("Expected the functions");
+        };
+        assert!(funcs.contains("f(a, b)\n"));
+        assert!(funcs.contains("sin: takes 1 argument\n"));
+        let ast =
+            "Stmt\n  Expr\n    Add\n      Expr\n        Number 1\n      Expr\n        Number 2";
+        assert_eq!(session.handle(":ast 1 + 2"), output(ast));
+        assert!(matches!(session.handle(":ast 1 +"), Reply::Error(_)));
+    }
+
+    #[test]
+    fn settings() {
+        let mut session = session();
+        assert_eq!(session.handle(":precision"), output("off"));
+        assert_eq!(session.handle(":precision 2"), output(""));
+        assert_eq!(session.handle("1 / 3"), output("0.33"));
+        assert_eq!(session.handle(":precision off"), output(""));
+        assert!(matches!(session.handle(":precision two"), Reply::Error(_)));
+
+        assert_eq!(session.handle(":digits 3"), output(""));
+        assert_eq!(session.handle("2 / 3"), output("0.667"));
+        assert_eq!(session.handle(":format"), output("auto"));
+        assert_eq!(session.handle(":format eng"), output(""));
+        assert_eq!(session.handle("12345"), output("12.3e3"));
+        assert_eq!(session.handle(":precision 1"), output(""));
+        assert_eq!(session.handle(":digits"), output("off"));
+        assert_eq!(session.handle("12345"), output("12.3e3"));
+        assert!(matches!(session.handle(":digits 0"), Reply::Error(_)));
+        assert!(matches!(session.handle(":format exp"), Reply::Error(_)));
+        assert_eq!(session.handle(":format auto"), output(""));
+        assert_eq!(session.handle(":precision off"), output(""));
+
+        assert_eq!(session.handle("1 / 0"), output("inf"));
+        assert_eq!(session.handle(":strict on"), output(""));
+        assert!(matches!(session.handle("1 / 0"), Reply::Error(_)));
+        assert_eq!(session.handle(":strict"), output("on"));
+
+        let Reply::Error(error) = session.handle(":var") else {
+            panic!("Expected an error");
+        };
+        assert!(error.contains("did you mean `:vars`?"));
+        assert_eq!(session.handle(":quit"), Reply::Quit);
+    }
+
+    #[cfg(not(feature = "jit"))]
+    #[test]
+    fn jit_unavailable() {
+        let mut session = session();
+        assert!(matches!(session.handle(":jit on"), Reply::Error(_)));
+        assert_eq!(session.handle(":jit"), output("off"));
+    }
+
+    #[cfg(feature = "jit")]
+    #[test]
+    fn jit() {
+        let mut session = session();
+        session.handle("let x = 3");
+        session.handle("f(y) = x * y + rate");
+        session.handle("f(2)");
+        assert_eq!(session.handle(":jit on"), output(""));
+        // Definitions made before the JIT was turned on are replayed
+        assert_eq!(session.handle("f(ans) + x"), output("23"));
+        assert_eq!(session.handle("x = 1"), output("1"));
+        assert_eq!(session.handle("f(ans)"), output("1.5"));
+        assert_eq!(session.handle(":jit off"), output(""));
+        assert_eq!(session.handle("ans * 2"), output("3"));
+    }
+
+    #[cfg(feature = "jit")]
+    #[test]
+    fn jit_refuses() {
+        let mut session = session();
+        assert_eq!(session.handle(":jit on"), output(""));
+        // Neither backend defines what the JIT cannot compile
+        assert!(matches!(
+            session.handle("fac(n) = n * fac(n - 1)"),
+            Reply::Error(_)
+        ));
+        assert!(matches!(session.handle("f(x) = x + k"), Reply::Error(_)));
+        assert!(matches!(session.handle("fac(1)"), Reply::Error(_)));
+        assert_eq!(session.handle("let k = 2"), output("2"));
+        assert_eq!(session.handle("f(x) = x + k"), output(""));
+        assert_eq!(session.handle("f(1)"), output("3"));
+
+        // The interpreter alone accepts it, so the JIT cannot be switched on
+        assert_eq!(session.handle(":jit off"), output(""));
+        assert_eq!(session.handle("fac(n) = n * fac(n - 1)"), output(""));
+        assert!(matches!(session.handle(":jit on"), Reply::Error(_)));
+        assert_eq!(session.handle(":jit"), output("off"));
+        assert_eq!(session.handle("f(1)"), output("3"));
+    }
+}
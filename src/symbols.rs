@@ -1,8 +1,29 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-#[derive(Debug)]
-pub struct SymbolTable<T> {
+#[derive(Debug, Clone)]
+struct Scope<T> {
     map: HashMap<String, T>,
+    /// Descriptions of some of the bindings of `map`
+    docs: HashMap<String, String>,
+    parent: Option<usize>,
+}
+
+impl<T> Scope<T> {
+    fn new(parent: Option<usize>) -> Self {
+        Scope {
+            map: HashMap::new(),
+            docs: HashMap::new(),
+            parent,
+        }
+    }
+}
+
+/// Symbols organised as a stack of scopes. Lookups start at the innermost
+/// scope and follow the parent links until the global scope is reached, so an
+/// inner binding shadows an outer one with the same name.
+#[derive(Debug, Clone)]
+pub struct SymbolTable<T> {
+    scopes: Vec<Scope<T>>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -11,29 +32,133 @@ pub enum SymbolError {
     UnDefinition,
 }
 
-impl<T> SymbolTable<T>
-where
-    T: Copy,
-{
+impl<T> Default for SymbolTable<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> SymbolTable<T> {
     pub fn new() -> Self {
         SymbolTable {
-            map: HashMap::new(),
+            scopes: vec![Scope::new(None)],
         }
     }
 
+    /// Opens a scope nested in the innermost one, it sees every binding that
+    /// is visible at this point.
+    pub fn push_scope(&mut self) {
+        self.scopes.push(Scope::new(Some(self.scopes.len() - 1)));
+    }
+
+    /// Opens a scope whose parent is the global scope, so that a function body
+    /// sees its own parameters and the globals but never the caller's locals.
+    pub fn push_function_scope(&mut self) {
+        self.scopes.push(Scope::new(Some(0)));
+    }
+
+    /// Closes the innermost scope, the global scope is never popped.
+    pub fn pop_scope(&mut self) {
+        if self.scopes.len() > 1 {
+            self.scopes.pop();
+        }
+    }
+
+    /// Number of open scopes, the global scope included.
+    pub fn depth(&self) -> usize {
+        self.scopes.len()
+    }
+
+    /// Indices of the visible scopes, from the innermost to the global one.
+    fn chain(&self) -> impl Iterator<Item = usize> + '_ {
+        std::iter::successors(Some(self.scopes.len() - 1), |&i| self.scopes[i].parent)
+    }
+
+    fn lookup(&self, name: &str) -> Option<usize> {
+        self.chain()
+            .find(|&i| self.scopes[i].map.contains_key(name))
+    }
+
+    fn innermost(&mut self) -> &mut Scope<T> {
+        self.scopes.last_mut().unwrap()
+    }
+
+    /// Binds `name` in the innermost scope. It may shadow a binding of an outer
+    /// scope but not one of the same scope.
     pub fn define(&mut self, name: &str, value: T) -> Result<(), SymbolError> {
-        if self.map.contains_key(name) {
+        let scope = self.innermost();
+        if scope.map.contains_key(name) {
             return Err(SymbolError::ReDefinition);
         }
-        self.map.insert(name.into(), value);
+        scope.map.insert(name.into(), value);
         Ok(())
     }
 
-    pub fn get(&mut self, name: &str) -> Result<T, SymbolError> {
-        if !self.map.contains_key(name) {
-            return Err(SymbolError::UnDefinition);
+    /// Rebinds the visible symbol `name` in whichever scope it lives in.
+    pub fn set(&mut self, name: &str, value: T) -> Result<(), SymbolError> {
+        match self.lookup(name) {
+            Some(i) => {
+                self.scopes[i].map.insert(name.into(), value);
+                Ok(())
+            }
+            None => Err(SymbolError::UnDefinition),
+        }
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.lookup(name).is_some()
+    }
+
+    pub fn get(&self, name: &str) -> Result<&T, SymbolError> {
+        match self.lookup(name) {
+            Some(i) => Ok(&self.scopes[i].map[name]),
+            None => Err(SymbolError::UnDefinition),
         }
-        Ok(*self.map.get(name).unwrap())
+    }
+
+    /// Removes the visible binding of `name`, which uncovers any binding it
+    /// was shadowing.
+    pub fn remove(&mut self, name: &str) -> Result<T, SymbolError> {
+        match self.lookup(name) {
+            Some(i) => {
+                self.scopes[i].docs.remove(name);
+                Ok(self.scopes[i].map.remove(name).unwrap())
+            }
+            None => Err(SymbolError::UnDefinition),
+        }
+    }
+
+    /// Iterates over the visible bindings, shadowed ones are skipped.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &T)> + '_ {
+        let mut seen = HashSet::new();
+        self.chain()
+            .flat_map(|i| self.scopes[i].map.iter())
+            .filter(move |(name, _)| seen.insert(name.as_str()))
+            .map(|(name, value)| (name.as_str(), value))
+    }
+
+    /// Names of the visible bindings.
+    pub fn names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.iter().map(|(name, _)| name)
+    }
+
+    /// Attaches a one-line description to the visible binding of `name`. It
+    /// stays with the binding when the value is set again, and goes away
+    /// with it.
+    pub fn set_doc(&mut self, name: &str, doc: impl Into<String>) -> Result<(), SymbolError> {
+        match self.lookup(name) {
+            Some(i) => {
+                self.scopes[i].docs.insert(name.into(), doc.into());
+                Ok(())
+            }
+            None => Err(SymbolError::UnDefinition),
+        }
+    }
+
+    /// Description of the visible binding of `name`, if it has one.
+    pub fn doc(&self, name: &str) -> Option<&str> {
+        let i = self.lookup(name)?;
+        self.scopes[i].docs.get(name).map(String::as_str)
     }
 }
 
@@ -51,7 +176,7 @@ mod tests {
         let a_result_from_symbols = symbols.get(name);
         assert!(a_result_from_symbols.is_ok());
         if let Ok(a_from_symbols) = a_result_from_symbols {
-            assert_close(a_from_symbols, a);
+            assert_close(*a_from_symbols, a);
         }
     }
 
@@ -67,7 +192,109 @@ mod tests {
     #[test]
     fn undefined() {
         let name = "a";
-        let mut symbols = SymbolTable::<f64>::new();
+        let symbols = SymbolTable::<f64>::new();
         assert_eq!(symbols.get(name), Err(SymbolError::UnDefinition));
     }
+
+    #[test]
+    fn rebind() {
+        let name = "a";
+        let mut symbols = SymbolTable::<f64>::new();
+        assert_eq!(symbols.set(name, 1.0), Err(SymbolError::UnDefinition));
+        assert!(!symbols.contains(name));
+        assert_eq!(symbols.define(name, 1.0), Ok(()));
+        assert!(symbols.contains(name));
+        assert_eq!(symbols.set(name, 2.0), Ok(()));
+        assert_close(*symbols.get(name).unwrap(), 2.0);
+        assert_eq!(symbols.define(name, 3.0), Err(SymbolError::ReDefinition));
+        assert_close(*symbols.get(name).unwrap(), 2.0);
+    }
+
+    #[test]
+    fn function_scope() {
+        let mut symbols = SymbolTable::<f64>::new();
+        assert_eq!(symbols.define("g", 1.0), Ok(()));
+        symbols.push_function_scope();
+        assert_eq!(symbols.define("x", 2.0), Ok(()));
+        assert_eq!(symbols.define("g", 3.0), Ok(()));
+        assert_close(*symbols.get("g").unwrap(), 3.0);
+        // A nested call only sees the globals, not the locals of its caller
+        symbols.push_function_scope();
+        assert_eq!(symbols.get("x"), Err(SymbolError::UnDefinition));
+        assert_close(*symbols.get("g").unwrap(), 1.0);
+        symbols.pop_scope();
+        symbols.pop_scope();
+        assert_eq!(symbols.get("x"), Err(SymbolError::UnDefinition));
+        assert_close(*symbols.get("g").unwrap(), 1.0);
+        symbols.pop_scope();
+        assert_close(*symbols.get("g").unwrap(), 1.0);
+    }
+
+    #[test]
+    fn nested_scope() {
+        let mut symbols = SymbolTable::<f64>::new();
+        assert_eq!(symbols.define("a", 1.0), Ok(()));
+        assert_eq!(symbols.define("b", 2.0), Ok(()));
+        symbols.push_scope();
+        assert_eq!(symbols.depth(), 2);
+        // Shadowing is allowed across scopes, lookups see through the parents
+        assert_eq!(symbols.define("a", 10.0), Ok(()));
+        assert_close(*symbols.get("a").unwrap(), 10.0);
+        assert_close(*symbols.get("b").unwrap(), 2.0);
+        // `set` updates the binding where it lives
+        assert_eq!(symbols.set("b", 20.0), Ok(()));
+        symbols.push_scope();
+        assert_eq!(symbols.define("c", 3.0), Ok(()));
+        let mut visible = symbols.iter().map(|(n, v)| (n, *v)).collect::<Vec<_>>();
+        visible.sort_by(|x, y| x.0.cmp(y.0));
+        assert_eq!(visible, vec![("a", 10.0), ("b", 20.0), ("c", 3.0)]);
+        symbols.pop_scope();
+        symbols.pop_scope();
+        assert_eq!(symbols.depth(), 1);
+        assert_close(*symbols.get("a").unwrap(), 1.0);
+        assert_close(*symbols.get("b").unwrap(), 20.0);
+        assert_eq!(symbols.get("c"), Err(SymbolError::UnDefinition));
+    }
+
+    #[test]
+    fn remove() {
+        let mut symbols = SymbolTable::<f64>::new();
+        assert_eq!(symbols.define("a", 1.0), Ok(()));
+        symbols.push_scope();
+        assert_eq!(symbols.define("a", 2.0), Ok(()));
+        assert_eq!(symbols.remove("a"), Ok(2.0));
+        assert_close(*symbols.get("a").unwrap(), 1.0);
+        assert_eq!(symbols.remove("a"), Ok(1.0));
+        assert_eq!(symbols.remove("a"), Err(SymbolError::UnDefinition));
+        assert!(!symbols.contains("a"));
+        assert_eq!(symbols.iter().count(), 0);
+    }
+
+    #[test]
+    fn docs() {
+        let mut symbols = SymbolTable::<f64>::new();
+        assert_eq!(symbols.define("a", 1.0), Ok(()));
+        assert_eq!(symbols.set_doc("a", "the first one"), Ok(()));
+        assert_eq!(
+            symbols.set_doc("b", "missing"),
+            Err(SymbolError::UnDefinition)
+        );
+        assert_eq!(symbols.set("a", 2.0), Ok(()));
+        assert_eq!(symbols.doc("a"), Some("the first one"));
+
+        // A shadowing binding has its own description
+        symbols.push_scope();
+        assert_eq!(symbols.define("a", 3.0), Ok(()));
+        assert_eq!(symbols.define("b", 4.0), Ok(()));
+        assert_eq!(symbols.doc("a"), None);
+        let mut names = symbols.names().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["a", "b"]);
+        symbols.pop_scope();
+        assert_eq!(symbols.doc("a"), Some("the first one"));
+
+        assert_eq!(symbols.remove("a"), Ok(2.0));
+        assert_eq!(symbols.define("a", 5.0), Ok(()));
+        assert_eq!(symbols.doc("a"), None);
+    }
 }
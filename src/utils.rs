@@ -1,4 +1,90 @@
-#[allow(dead_code)]
 pub fn assert_close(a: f64, b: f64) {
     assert!(f64::abs(a - b) < 1e-6);
 }
+
+/// Modulo and division cases shared by the interpreter and JIT tests, so both
+/// backends are checked against the same semantics for negative operands.
+#[cfg(test)]
+pub const MODULO_CASES: &[(&str, f64)] = &[
+    ("7 % 3", 1.0),
+    ("-7 % 3", 2.0),
+    ("7 % -3", -2.0),
+    ("-7 % -3", -1.0),
+    ("6 % -3", 0.0),
+    ("5.5 % 2", 1.5),
+    ("7 // 3", 2.0),
+    ("-7 // 3", -3.0),
+    ("7 // -3", -3.0),
+    ("-7 // -3", 2.0),
+    ("7 rem 3", 1.0),
+    ("-7 rem 3", 2.0),
+    ("7 rem -3", 1.0),
+    ("-7 rem -3", 2.0),
+    ("-5.5 rem 2", 0.5),
+    ("2 * 7 % 4", 2.0),
+];
+
+/// Calls to every preset function and their values, evaluated by both
+/// backends in the parity tests.
+#[cfg(test)]
+pub const PRESET_CASES: &[(&str, f64)] = &[
+    ("log(2, 8)", 3.0),
+    ("log(10, 0.001)", -3.0),
+    ("ln(E)", 1.0),
+    ("ln(0)", f64::NEG_INFINITY),
+    ("log_2(1024)", 10.0),
+    ("log_10(0.001)", -3.0),
+    ("add(1, 2)", 3.0),
+    ("sum()", 0.0),
+    ("sum(1)", 1.0),
+    ("sum(1, 2, 3.5)", 6.5),
+    ("pow(2, 0.5)", std::f64::consts::SQRT_2),
+    ("pow(-8, 1 / 3)", f64::NAN),
+    ("sqrt(2)", std::f64::consts::SQRT_2),
+    ("sqrt(-1)", f64::NAN),
+    ("max()", f64::NAN),
+    ("max(3, -1, 7)", 7.0),
+    ("max(sqrt(-1), 2)", 2.0),
+    ("min()", f64::NAN),
+    ("min(3, -1, 7)", -1.0),
+    ("min(2, sqrt(-1))", 2.0),
+    ("sin(PI / 6)", 0.5),
+    ("cos(TAU / 3)", -0.5),
+    ("tan(1)", 1.557_407_724_654_902_3),
+    ("floor(-2.5)", -3.0),
+    ("ceil(-2.5)", -2.0),
+    ("abs(-3)", 3.0),
+    (
+        "sum(max(1, 2), min(3, 4), abs(-5)) * sqrt(PI)",
+        17.724_538_509_055_16,
+    ),
+];
+
+/// Factorial edge cases and their values. NaN stands for a domain error in
+/// the interpreter, the JIT gives NaN instead.
+#[cfg(test)]
+pub const FACTORIAL_CASES: &[(&str, f64)] = &[
+    ("0!", 1.0),
+    ("1!", 1.0),
+    ("5!", 120.0),
+    ("5.9!", 120.0),
+    ("0.5!", 1.0),
+    ("-0!", -1.0),
+    ("20!", 2_432_902_008_176_640_000.0),
+    ("170!", 7.257_415_615_307_994e306),
+    ("171!", f64::INFINITY),
+    ("1000!", f64::INFINITY),
+    ("(-3)!", f64::NAN),
+    ("(-0.5)!", f64::NAN),
+    ("(0 / 0)!", f64::NAN),
+    ("3!!", 720.0),
+    ("2^3!", 64.0),
+    ("-3!", -6.0),
+];
+
+/// Like `assert_close`, but NaN matches NaN and infinities must be equal.
+#[cfg(test)]
+pub fn assert_same(input: &str, a: f64, b: f64) {
+    let same = a == b || (a.is_nan() && b.is_nan()) || f64::abs(a - b) < 1e-9;
+    assert!(same, "`{input}` gives {a} and {b}");
+}
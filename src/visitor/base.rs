@@ -1,9 +1,10 @@
-use crate::ast::{Atom, BinaryArithmetic, Expr, FunctionCall, UnaryArithmetic};
+use crate::ast::{Atom, BinaryArithmetic, Expr, FunctionCall, Span, Stmt, UnaryArithmetic};
 
 pub trait Visitor<T> {
+    fn visit_stmt(&mut self, s: &Stmt) -> T;
     fn visit_expr(&mut self, e: &Expr) -> T;
     fn visit_unary(&mut self, u: &UnaryArithmetic) -> T;
     fn visit_binary(&mut self, b: &BinaryArithmetic) -> T;
     fn visit_function(&mut self, f: &FunctionCall) -> T;
-    fn visit_atom(&mut self, a: &Atom) -> T;
+    fn visit_atom(&mut self, a: &Atom, span: Span) -> T;
 }
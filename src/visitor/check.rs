@@ -0,0 +1,163 @@
+use super::{Arity, EvalError, Visitor};
+use crate::ast::{Atom, BinaryArithmetic, Expr, FunctionCall, Span, Stmt, UnaryArithmetic};
+use crate::symbols::SymbolTable;
+
+/// Static pass that finds every call whose number of arguments does not match
+/// the signature of the called function, without evaluating anything.
+///
+/// Calls to functions it does not know about are left alone, they may well be
+/// defined by the time the call is evaluated.
+#[derive(Debug)]
+pub struct ArityChecker {
+    signatures: SymbolTable<Arity>,
+    errors: Vec<EvalError>,
+}
+
+impl ArityChecker {
+    pub fn new() -> Self {
+        ArityChecker {
+            signatures: SymbolTable::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Declares the signature of `name`, replacing any previous one.
+    pub fn define_function(&mut self, name: &str, arity: Arity) {
+        if self.signatures.contains(name) {
+            self.signatures.set(name, arity).unwrap();
+        } else {
+            self.signatures.define(name, arity).unwrap();
+        }
+    }
+
+    /// Checks the statements in order, so that a function defined by the
+    /// program is known to the calls that follow its definition.
+    pub fn check(&mut self, program: &[Stmt]) -> Result<(), Vec<EvalError>> {
+        for stmt in program {
+            self.visit_stmt(stmt);
+        }
+        self.take_errors()
+    }
+
+    /// Checks a single expression, such as a formula compiled on its own.
+    pub fn check_expr(&mut self, e: &Expr) -> Result<(), Vec<EvalError>> {
+        self.visit_expr(e);
+        self.take_errors()
+    }
+
+    fn take_errors(&mut self) -> Result<(), Vec<EvalError>> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+}
+
+impl Default for ArityChecker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Visitor<()> for ArityChecker {
+    fn visit_stmt(&mut self, s: &Stmt) {
+        match s {
+            Stmt::Let(ref a) | Stmt::Assign(ref a) => self.visit_expr(&a.value),
+            Stmt::Function(ref def) => {
+                self.define_function(&def.name, Arity::Fixed(def.params.len()));
+                self.visit_expr(&def.body);
+            }
+            Stmt::Expr(ref e) => self.visit_expr(e),
+        }
+    }
+
+    fn visit_expr(&mut self, e: &Expr) {
+        match e {
+            Expr::UnaryArithmetic(ref u) => self.visit_unary(u),
+            Expr::BinaryArithmetic(ref b) => self.visit_binary(b),
+            Expr::FunctionCall(ref f) => self.visit_function(f),
+            Expr::Atom(ref a, span) => self.visit_atom(a, *span),
+        }
+    }
+
+    fn visit_unary(&mut self, u: &UnaryArithmetic) {
+        self.visit_expr(&u.value);
+    }
+
+    fn visit_binary(&mut self, b: &BinaryArithmetic) {
+        self.visit_expr(&b.lhs);
+        self.visit_expr(&b.rhs);
+    }
+
+    fn visit_function(&mut self, f: &FunctionCall) {
+        if let Ok(&arity) = self.signatures.get(&f.name) {
+            if !arity.accepts(f.args.len()) {
+                self.errors.push(EvalError::ArityMismatch {
+                    name: f.name.clone(),
+                    expected: arity,
+                    found: f.args.len(),
+                    span: f.span,
+                });
+            }
+        }
+        for arg in &f.args {
+            self.visit_expr(arg);
+        }
+    }
+
+    fn visit_atom(&mut self, _a: &Atom, _span: Span) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::calc_parser;
+    use crate::visitor::Calculator;
+
+    fn check(input: &str) -> Result<(), Vec<EvalError>> {
+        let mut calculator = Calculator::new();
+        calculator.preset().unwrap();
+        let program = calc_parser::program(input).unwrap();
+        calculator.arity_checker().check(&program)
+    }
+
+    #[test]
+    fn valid() {
+        assert_eq!(check("sin(1) + log(2, 8) + sum() + max(1, 2, 3)"), Ok(()));
+        assert_eq!(check("unknown(1, 2, 3)"), Ok(()));
+        assert_eq!(check("f(x) = sin(x); f(1)"), Ok(()));
+    }
+
+    #[test]
+    fn reports_every_mismatch() {
+        let errors = check("sin() + cos(1, sqrt(1, 2))").unwrap_err();
+        let found = errors
+            .iter()
+            .map(|e| match e {
+                EvalError::ArityMismatch {
+                    name, found, span, ..
+                } => (name.as_str(), *found, span.start, span.end),
+                _ => panic!("unexpected error {e:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            vec![("sin", 0, 0, 5), ("cos", 2, 8, 26), ("sqrt", 2, 15, 25)]
+        );
+    }
+
+    #[test]
+    fn user_functions() {
+        let errors = check("f(x, y) = x * y; f(1)\nf(x) = x; f(1)").unwrap_err();
+        assert_eq!(
+            errors,
+            vec![EvalError::ArityMismatch {
+                name: "f".into(),
+                expected: Arity::Fixed(2),
+                found: 1,
+                span: Span::new(17, 21),
+            }]
+        );
+    }
+}
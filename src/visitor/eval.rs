@@ -1,159 +1,654 @@
+use super::ArityChecker;
 use super::Visitor;
-use crate::ast::{Atom, BinaryArithmetic, BinaryOp, Expr, FunctionCall, UnaryArithmetic, UnaryOp};
-use crate::symbols::{SymbolError, SymbolTable};
+use crate::ast::{
+    Atom, BinaryArithmetic, BinaryOp, Expr, FunctionCall, FunctionDef, Span, Stmt, UnaryArithmetic,
+    UnaryOp,
+};
+use crate::diagnostics::did_you_mean;
+use crate::symbols::SymbolTable;
 use std::f64::consts;
+use std::fmt;
+use std::rc::Rc;
 
-pub type Func<T> = fn(Vec<T>) -> T;
+/// A native function, it may capture state such as lookup tables or handles
+/// owned by the host application.
+pub type Func<T> = Rc<dyn Fn(&[T]) -> Result<T, EvalError>>;
 
-#[derive(Debug)]
-pub struct Calculator {
-    variables: SymbolTable<f64>,
-    functions: SymbolTable<Func<f64>>,
-    operand_stack: Vec<f64>,
+/// Nesting limit for calls to user-defined functions. The language has no
+/// conditionals, so reaching it means the definitions recurse forever.
+const MAX_CALL_DEPTH: usize = 256;
+
+const PRESET_VARIABLE_DOCS: &[(&str, &str)] = &[
+    ("PI", "ratio of a circle's circumference to its diameter"),
+    (
+        "TAU",
+        "ratio of a circle's circumference to its radius, 2 PI",
+    ),
+    ("E", "Euler's number, base of the natural logarithm"),
+];
+
+const PRESET_FUNCTION_DOCS: &[(&str, &str)] = &[
+    ("log", "log(base, x), logarithm of x in the given base"),
+    ("ln", "natural logarithm"),
+    ("log_2", "base 2 logarithm"),
+    ("log_10", "base 10 logarithm"),
+    ("add", "sum of the two arguments"),
+    ("sum", "sum of the arguments, 0 without any"),
+    ("pow", "pow(x, y), x raised to the power y"),
+    ("sqrt", "square root"),
+    ("max", "largest argument, NaN without any"),
+    ("min", "smallest argument, NaN without any"),
+    ("sin", "sine of an angle in radians"),
+    ("cos", "cosine of an angle in radians"),
+    ("tan", "tangent of an angle in radians"),
+    (
+        "floor",
+        "largest integer less than or equal to the argument",
+    ),
+    (
+        "ceil",
+        "smallest integer greater than or equal to the argument",
+    ),
+    ("abs", "absolute value"),
+];
+
+/// Number of arguments a function accepts.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Arity {
+    Fixed(usize),
+    /// Between the two bounds, both inclusive
+    Range(usize, usize),
+    Variadic,
+}
+
+impl Arity {
+    pub fn accepts(&self, argc: usize) -> bool {
+        match self {
+            Arity::Fixed(n) => *n == argc,
+            Arity::Range(min, max) => (*min..=*max).contains(&argc),
+            Arity::Variadic => true,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Arity::Fixed(1) => write!(f, "1 argument"),
+            Arity::Fixed(n) => write!(f, "{n} arguments"),
+            Arity::Range(min, max) => write!(f, "{min} to {max} arguments"),
+            Arity::Variadic => write!(f, "any number of arguments"),
+        }
+    }
 }
 
-#[derive(Debug)]
-pub enum CalculatorError {
-    StackEmpty,
+#[derive(Debug, PartialEq, Clone)]
+pub enum EvalError {
+    UnknownVariable {
+        name: String,
+        span: Span,
+    },
+    UnknownFunction {
+        name: String,
+        span: Span,
+    },
+    ReDefinition(String),
+    ArityMismatch {
+        name: String,
+        expected: Arity,
+        found: usize,
+        span: Span,
+    },
+    Domain {
+        name: String,
+        value: f64,
+        span: Span,
+    },
+    /// Only raised in strict mode, otherwise dividing by zero gives infinity or NaN
+    DivisionByZero {
+        span: Span,
+    },
+    RecursionLimit {
+        name: String,
+        span: Span,
+    },
+    /// Only raised by the JIT backend, which cannot compile recursive functions
+    UnsupportedRecursion {
+        name: String,
+        span: Span,
+    },
+    StackUnderflow,
     StackNotEmpty,
 }
 
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::UnknownVariable { name, .. } => write!(f, "unknown variable `{name}`"),
+            EvalError::UnknownFunction { name, .. } => write!(f, "unknown function `{name}`"),
+            EvalError::ReDefinition(name) => write!(f, "`{name}` is already defined"),
+            EvalError::ArityMismatch {
+                name,
+                expected,
+                found,
+                ..
+            } => {
+                let verb = if *found == 1 { "was" } else { "were" };
+                write!(
+                    f,
+                    "function `{name}` takes {expected} but {found} {verb} given"
+                )
+            }
+            EvalError::Domain { name, value, .. } => {
+                write!(f, "`{name}` is undefined for {value}")
+            }
+            EvalError::DivisionByZero { .. } => write!(f, "division by zero"),
+            EvalError::RecursionLimit { name, .. } => {
+                write!(f, "maximum call depth exceeded while calling `{name}`")
+            }
+            EvalError::UnsupportedRecursion { name, .. } => {
+                write!(
+                    f,
+                    "recursive call to `{name}` is not supported by the JIT backend"
+                )
+            }
+            EvalError::StackUnderflow => write!(f, "operand stack underflow"),
+            EvalError::StackNotEmpty => write!(f, "operand stack is not empty after evaluation"),
+        }
+    }
+}
+
+impl EvalError {
+    /// Location of the sub-expression that caused the error, if known.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            EvalError::UnknownVariable { span, .. }
+            | EvalError::UnknownFunction { span, .. }
+            | EvalError::ArityMismatch { span, .. }
+            | EvalError::Domain { span, .. }
+            | EvalError::DivisionByZero { span }
+            | EvalError::RecursionLimit { span, .. }
+            | EvalError::UnsupportedRecursion { span, .. } => Some(*span),
+            EvalError::ReDefinition(_) | EvalError::StackUnderflow | EvalError::StackNotEmpty => {
+                None
+            }
+        }
+    }
+
+    /// Moves the error to `span`, errors without a location are unchanged.
+    pub fn with_span(mut self, span: Span) -> Self {
+        match self {
+            EvalError::UnknownVariable {
+                span: ref mut s, ..
+            }
+            | EvalError::UnknownFunction {
+                span: ref mut s, ..
+            }
+            | EvalError::ArityMismatch {
+                span: ref mut s, ..
+            }
+            | EvalError::Domain {
+                span: ref mut s, ..
+            }
+            | EvalError::DivisionByZero { span: ref mut s }
+            | EvalError::RecursionLimit {
+                span: ref mut s, ..
+            }
+            | EvalError::UnsupportedRecursion {
+                span: ref mut s, ..
+            } => *s = span,
+            EvalError::ReDefinition(_) | EvalError::StackUnderflow | EvalError::StackNotEmpty => {}
+        }
+        self
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+#[derive(Clone)]
+pub enum Function {
+    Native(Arity, Func<f64>),
+    User(Rc<FunctionDef>),
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Function::Native(arity, _) => f.debug_tuple("Native").field(arity).finish(),
+            Function::User(def) => f.debug_tuple("User").field(def).finish(),
+        }
+    }
+}
+
+impl Function {
+    pub fn arity(&self) -> Arity {
+        match self {
+            Function::Native(arity, _) => *arity,
+            Function::User(def) => Arity::Fixed(def.params.len()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Calculator {
+    variables: SymbolTable<f64>,
+    functions: SymbolTable<Function>,
+    operand_stack: Vec<f64>,
+    call_depth: usize,
+    strict: bool,
+    /// User function whose body raised the last error
+    failed_in: Option<String>,
+}
+
 impl Calculator {
     pub fn new() -> Self {
         Calculator {
             variables: SymbolTable::new(),
             functions: SymbolTable::new(),
             operand_stack: Vec::new(),
+            call_depth: 0,
+            strict: false,
+            failed_in: None,
+        }
+    }
+
+    /// In strict mode a division by zero is an error instead of producing
+    /// infinity or NaN.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    pub fn define_variable(&mut self, name: &str, value: f64) -> Result<(), EvalError> {
+        self.variables
+            .define(name, value)
+            .map_err(|_| EvalError::ReDefinition(name.into()))
+    }
+
+    /// Binds `name` to `value`, replacing any previous value.
+    pub fn assign_variable(&mut self, name: &str, value: f64) -> Result<(), EvalError> {
+        match self.variables.set(name, value) {
+            Ok(()) => Ok(()),
+            Err(_) => self.define_variable(name, value),
+        }
+    }
+
+    /// Attaches a one-line description to the variable `name`.
+    pub fn describe_variable(&mut self, name: &str, doc: &str) -> Result<(), EvalError> {
+        self.variables
+            .set_doc(name, doc)
+            .map_err(|_| EvalError::UnknownVariable {
+                name: name.into(),
+                span: Span::default(),
+            })
+    }
+
+    /// Attaches a one-line description to the function `name`.
+    pub fn describe_function(&mut self, name: &str, doc: &str) -> Result<(), EvalError> {
+        self.functions
+            .set_doc(name, doc)
+            .map_err(|_| EvalError::UnknownFunction {
+                name: name.into(),
+                span: Span::default(),
+            })
+    }
+
+    pub fn variable_doc(&self, name: &str) -> Option<&str> {
+        self.variables.doc(name)
+    }
+
+    pub fn function_doc(&self, name: &str) -> Option<&str> {
+        self.functions.doc(name)
+    }
+
+    /// Visible variables and their values.
+    pub fn variables(&self) -> impl Iterator<Item = (&str, f64)> + '_ {
+        self.variables.iter().map(|(name, value)| (name, *value))
+    }
+
+    /// Visible functions, native and user-defined.
+    pub fn functions(&self) -> impl Iterator<Item = (&str, &Function)> + '_ {
+        self.functions.iter()
+    }
+
+    /// Registers a native function. The evaluator checks the number of
+    /// arguments against `arity` before `func` is called.
+    pub fn define_function<F>(&mut self, name: &str, arity: Arity, func: F) -> Result<(), EvalError>
+    where
+        F: Fn(&[f64]) -> Result<f64, EvalError> + 'static,
+    {
+        self.functions
+            .define(name, Function::Native(arity, Rc::new(func)))
+            .map_err(|_| EvalError::ReDefinition(name.into()))
+    }
+
+    /// Registers a function written in the expression language. A later
+    /// definition with the same name replaces it, native functions cannot be
+    /// replaced.
+    pub fn define_user_function(&mut self, def: &FunctionDef) -> Result<(), EvalError> {
+        let func = Function::User(Rc::new(def.clone()));
+        match self.functions.get(&def.name) {
+            Ok(Function::User(_)) => self.functions.set(&def.name, func),
+            _ => self.functions.define(&def.name, func),
+        }
+        .map_err(|_| EvalError::ReDefinition(def.name.clone()))
+    }
+
+    /// Creates a checker that knows the signature of every function defined
+    /// so far.
+    pub fn arity_checker(&self) -> ArityChecker {
+        let mut checker = ArityChecker::new();
+        for (name, func) in self.functions.iter() {
+            checker.define_function(name, func.arity());
         }
+        checker
     }
 
-    pub fn define_variable(&mut self, name: &str, value: f64) -> Result<(), SymbolError> {
-        self.variables.define(name, value)
+    /// Finds the closest known name to the one an unknown-name error
+    /// complains about, for "did you mean" hints.
+    pub fn suggest(&self, error: &EvalError) -> Option<&str> {
+        match error {
+            EvalError::UnknownVariable { name, .. } => did_you_mean(name, self.variables.names()),
+            EvalError::UnknownFunction { name, .. } => did_you_mean(name, self.functions.names()),
+            _ => None,
+        }
     }
 
-    pub fn define_function(&mut self, name: &str, value: Func<f64>) -> Result<(), SymbolError> {
-        self.functions.define(name, value)
+    /// Names of the constants that `preset` defines.
+    pub fn preset_variables() -> impl Iterator<Item = &'static str> {
+        PRESET_VARIABLE_DOCS.iter().map(|(name, _)| *name)
     }
 
-    pub fn preset(&mut self) -> Result<(), SymbolError> {
+    pub fn preset(&mut self) -> Result<(), EvalError> {
         self.define_variable("PI", consts::PI)?;
         self.define_variable("TAU", consts::TAU)?;
         self.define_variable("E", consts::E)?;
 
-        self.define_function("log", |argv| f64::log(argv[1], argv[0]))?;
-        self.define_function("ln", |argv| f64::ln(argv[0]))?;
-        self.define_function("log_2", |argv| f64::log2(argv[0]))?;
-        self.define_function("log_10", |argv| f64::log10(argv[0]))?;
-        self.define_function("add", |argv| argv[0] + argv[1])?;
-        self.define_function("sum", |argv| argv.iter().sum())?;
-        self.define_function("pow", |argv| f64::powf(argv[0], argv[1]))?;
-        self.define_function("sqrt", |argv| f64::sqrt(argv[0]))?;
-        self.define_function("max", |argv| argv.iter().copied().fold(f64::NAN, f64::max))?;
-        self.define_function("min", |argv| argv.iter().copied().fold(f64::NAN, f64::min))?;
-        self.define_function("sin", |argv| f64::sin(argv[0]))?;
-        self.define_function("cos", |argv| f64::cos(argv[0]))?;
-        self.define_function("tan", |argv| f64::tan(argv[0]))?;
-        self.define_function("floor", |argv| f64::floor(argv[0]))?;
-        self.define_function("ceil", |argv| f64::ceil(argv[0]))?;
-        self.define_function("abs", |argv| f64::abs(argv[0]))?;
+        self.define_function("log", Arity::Fixed(2), |argv| {
+            Ok(f64::log(argv[1], argv[0]))
+        })?;
+        self.define_function("ln", Arity::Fixed(1), |argv| Ok(f64::ln(argv[0])))?;
+        self.define_function("log_2", Arity::Fixed(1), |argv| Ok(f64::log2(argv[0])))?;
+        self.define_function("log_10", Arity::Fixed(1), |argv| Ok(f64::log10(argv[0])))?;
+        self.define_function("add", Arity::Fixed(2), |argv| Ok(argv[0] + argv[1]))?;
+        self.define_function("sum", Arity::Variadic, |argv| Ok(argv.iter().sum()))?;
+        self.define_function("pow", Arity::Fixed(2), |argv| {
+            Ok(f64::powf(argv[0], argv[1]))
+        })?;
+        self.define_function("sqrt", Arity::Fixed(1), |argv| Ok(f64::sqrt(argv[0])))?;
+        self.define_function("max", Arity::Variadic, |argv| {
+            Ok(argv.iter().copied().fold(f64::NAN, f64::max))
+        })?;
+        self.define_function("min", Arity::Variadic, |argv| {
+            Ok(argv.iter().copied().fold(f64::NAN, f64::min))
+        })?;
+        self.define_function("sin", Arity::Fixed(1), |argv| Ok(f64::sin(argv[0])))?;
+        self.define_function("cos", Arity::Fixed(1), |argv| Ok(f64::cos(argv[0])))?;
+        self.define_function("tan", Arity::Fixed(1), |argv| Ok(f64::tan(argv[0])))?;
+        self.define_function("floor", Arity::Fixed(1), |argv| Ok(f64::floor(argv[0])))?;
+        self.define_function("ceil", Arity::Fixed(1), |argv| Ok(f64::ceil(argv[0])))?;
+        self.define_function("abs", Arity::Fixed(1), |argv| Ok(f64::abs(argv[0])))?;
+
+        for (name, doc) in PRESET_VARIABLE_DOCS {
+            self.describe_variable(name, doc)?;
+        }
+        for (name, doc) in PRESET_FUNCTION_DOCS {
+            self.describe_function(name, doc)?;
+        }
         Ok(())
     }
 
-    pub fn result(&mut self) -> Result<f64, CalculatorError> {
-        let value = match self.operand_stack.pop() {
-            Some(value) => Ok(value),
-            None => Err(CalculatorError::StackEmpty),
-        };
+    pub fn result(&mut self) -> Result<f64, EvalError> {
+        let value = self.pop()?;
         if !self.operand_stack.is_empty() {
-            return Err(CalculatorError::StackNotEmpty);
+            self.operand_stack.clear();
+            return Err(EvalError::StackNotEmpty);
+        }
+        Ok(value)
+    }
+
+    /// The user function whose body raised the last error of `eval` or
+    /// `run`, if the error did not come from the evaluated code itself. The
+    /// span of such an error points into the source that defined the function.
+    pub fn failed_function(&self) -> Option<&str> {
+        self.failed_in.as_deref()
+    }
+
+    /// Evaluates `e` and returns its value, leaving the operand stack empty.
+    pub fn eval(&mut self, e: &Expr) -> Result<f64, EvalError> {
+        self.failed_in = None;
+        if let Err(err) = self.visit_expr(e) {
+            self.operand_stack.clear();
+            return Err(err);
+        }
+        self.result()
+    }
+
+    /// Runs every statement of `program` in order and returns the value of the
+    /// last one that produces a value. Bindings made by the program stay
+    /// visible to later calls.
+    pub fn run(&mut self, program: &[Stmt]) -> Result<Option<f64>, EvalError> {
+        self.failed_in = None;
+        let mut value = None;
+        for stmt in program {
+            if let Err(err) = self.visit_stmt(stmt) {
+                self.operand_stack.clear();
+                return Err(err);
+            }
+            if !matches!(stmt, Stmt::Function(_)) {
+                value = Some(self.result()?);
+            }
         }
-        value
+        Ok(value)
+    }
+
+    fn call_user_function(
+        &mut self,
+        def: &FunctionDef,
+        argv: Vec<f64>,
+        span: Span,
+    ) -> Result<(), EvalError> {
+        if self.call_depth >= MAX_CALL_DEPTH {
+            return Err(EvalError::RecursionLimit {
+                name: def.name.clone(),
+                span,
+            });
+        }
+        self.call_depth += 1;
+        self.variables.push_function_scope();
+        let result = def
+            .params
+            .iter()
+            .zip(argv)
+            .try_for_each(|(param, value)| self.define_variable(param, value))
+            .and_then(|_| self.visit_expr(&def.body));
+        self.variables.pop_scope();
+        self.call_depth -= 1;
+        // The innermost function is the first to see the error
+        if result.is_err() && self.failed_in.is_none() {
+            self.failed_in = Some(def.name.clone());
+        }
+        result
+    }
+
+    fn pop(&mut self) -> Result<f64, EvalError> {
+        self.operand_stack.pop().ok_or(EvalError::StackUnderflow)
+    }
+}
+
+impl Default for Calculator {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-impl Visitor<()> for Calculator {
-    fn visit_expr(&mut self, e: &Expr) {
+impl Visitor<Result<(), EvalError>> for Calculator {
+    fn visit_stmt(&mut self, s: &Stmt) -> Result<(), EvalError> {
+        match s {
+            Stmt::Let(ref a) => {
+                self.visit_expr(&a.value)?;
+                let value = *self.operand_stack.last().ok_or(EvalError::StackUnderflow)?;
+                self.define_variable(&a.name, value)
+            }
+            Stmt::Assign(ref a) => {
+                self.visit_expr(&a.value)?;
+                let value = *self.operand_stack.last().ok_or(EvalError::StackUnderflow)?;
+                self.assign_variable(&a.name, value)
+            }
+            Stmt::Function(ref def) => self.define_user_function(def),
+            Stmt::Expr(ref e) => self.visit_expr(e),
+        }
+    }
+
+    fn visit_expr(&mut self, e: &Expr) -> Result<(), EvalError> {
         match e {
             Expr::UnaryArithmetic(ref u) => self.visit_unary(u),
             Expr::BinaryArithmetic(ref b) => self.visit_binary(b),
             Expr::FunctionCall(ref f) => self.visit_function(f),
-            Expr::Atom(ref a) => self.visit_atom(a),
+            Expr::Atom(ref a, span) => self.visit_atom(a, *span),
         }
     }
 
-    fn visit_unary(&mut self, u: &UnaryArithmetic) {
-        self.visit_expr(&u.value);
+    fn visit_unary(&mut self, u: &UnaryArithmetic) -> Result<(), EvalError> {
+        self.visit_expr(&u.value)?;
         match u.op {
             UnaryOp::Pos => (),
             UnaryOp::Neg => {
-                let value = self.operand_stack.pop();
-                self.operand_stack.push(-value.unwrap());
+                let value = self.pop()?;
+                self.operand_stack.push(-value);
             }
             UnaryOp::Fac => {
-                let value = self.operand_stack.pop();
-                self.operand_stack
-                    .push(factorial(value.unwrap() as u64) as f64);
+                let value = self.pop()?;
+                let result = factorial(value).ok_or(EvalError::Domain {
+                    name: "!".into(),
+                    value,
+                    span: u.span,
+                })?;
+                self.operand_stack.push(result);
             }
         }
+        Ok(())
     }
 
-    fn visit_binary(&mut self, b: &BinaryArithmetic) {
-        self.visit_expr(&b.lhs);
-        self.visit_expr(&b.rhs);
+    fn visit_binary(&mut self, b: &BinaryArithmetic) -> Result<(), EvalError> {
+        self.visit_expr(&b.lhs)?;
+        self.visit_expr(&b.rhs)?;
 
-        let rhs = self.operand_stack.pop().unwrap();
-        let lhs = self.operand_stack.pop().unwrap();
+        let rhs = self.pop()?;
+        let lhs = self.pop()?;
+        let is_division = matches!(
+            b.op,
+            BinaryOp::Div | BinaryOp::Mod | BinaryOp::FloorDiv | BinaryOp::Rem
+        );
+        if self.strict && is_division && rhs == 0.0 {
+            return Err(EvalError::DivisionByZero { span: b.span });
+        }
         match b.op {
             BinaryOp::Add => self.operand_stack.push(lhs + rhs),
             BinaryOp::Sub => self.operand_stack.push(lhs - rhs),
             BinaryOp::Mul => self.operand_stack.push(lhs * rhs),
             BinaryOp::Div => self.operand_stack.push(lhs / rhs),
+            BinaryOp::Mod => self.operand_stack.push(floored_mod(lhs, rhs)),
+            BinaryOp::FloorDiv => self.operand_stack.push((lhs / rhs).floor()),
+            BinaryOp::Rem => self.operand_stack.push(lhs.rem_euclid(rhs)),
+            BinaryOp::Pow => self.operand_stack.push(lhs.powf(rhs)),
         }
+        Ok(())
     }
 
-    fn visit_function(&mut self, f: &FunctionCall) {
+    fn visit_function(&mut self, f: &FunctionCall) -> Result<(), EvalError> {
         let argc = f.args.len();
+        let func_name = &f.name;
+        let func = self
+            .functions
+            .get(func_name)
+            .map_err(|_| EvalError::UnknownFunction {
+                name: func_name.clone(),
+                span: f.span,
+            })?
+            .clone();
+        let arity = func.arity();
+        if !arity.accepts(argc) {
+            return Err(EvalError::ArityMismatch {
+                name: func_name.clone(),
+                expected: arity,
+                found: argc,
+                span: f.span,
+            });
+        }
         for arg in &f.args {
-            self.visit_expr(arg);
+            self.visit_expr(arg)?;
         }
-        let func_name = &f.name;
-        let func = self.functions.get(func_name).unwrap();
-        let mut argv = Vec::new();
+        let mut argv = Vec::with_capacity(argc);
         for _ in 0..argc {
-            argv.push(self.operand_stack.pop().unwrap());
+            argv.push(self.pop()?);
         }
         argv.reverse();
-        self.operand_stack.push(func(argv));
+        match func {
+            Function::Native(_, func) => {
+                // Native functions cannot know where they were called from
+                let value = func(&argv).map_err(|e| e.with_span(f.span))?;
+                self.operand_stack.push(value);
+                Ok(())
+            }
+            Function::User(def) => self.call_user_function(&def, argv, f.span),
+        }
     }
 
-    fn visit_atom(&mut self, a: &Atom) {
+    fn visit_atom(&mut self, a: &Atom, span: Span) -> Result<(), EvalError> {
         match a {
-            Atom::Ident(ref id) => self.operand_stack.push(self.variables.get(id).unwrap()),
+            Atom::Ident(ref id) => {
+                let value =
+                    self.variables
+                        .get(id)
+                        .copied()
+                        .map_err(|_| EvalError::UnknownVariable {
+                            name: id.clone(),
+                            span,
+                        })?;
+                self.operand_stack.push(value);
+            }
             Atom::Number(ref n) => self.operand_stack.push(*n),
         }
+        Ok(())
+    }
+}
+
+/// Remainder of the division rounded towards negative infinity, so that the
+/// result has the same sign as `rhs`.
+fn floored_mod(lhs: f64, rhs: f64) -> f64 {
+    let rem = lhs % rhs;
+    if rem != 0.0 && (rem < 0.0) != (rhs < 0.0) {
+        rem + rhs
+    } else {
+        rem
     }
 }
 
-fn factorial(num: u64) -> u64 {
-    match (1..=num).reduce(|accum, item| accum * item) {
-        Some(x) => x,
-        None => num,
+/// Factorial of the integer part of `num`, saturating to infinity once the
+/// result no longer fits in an `f64`. Undefined for negative numbers and NaN.
+fn factorial(num: f64) -> Option<f64> {
+    if num.is_nan() || num < 0.0 {
+        return None;
     }
+    // 171! is the first factorial that overflows an f64
+    if num >= 171.0 {
+        return Some(f64::INFINITY);
+    }
+    Some((1..=num as u64).map(|x| x as f64).product())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::parser::calc_parser;
-    use crate::utils::assert_close;
+    use crate::utils::{assert_close, assert_same, FACTORIAL_CASES, MODULO_CASES, PRESET_CASES};
 
     #[test]
     fn calc_number() {
         let input = "2.333333333";
         let parsed_input = calc_parser::expr(input).unwrap();
         let mut calculator = Calculator::new();
-        calculator.visit_expr(&parsed_input);
-        assert_close(calculator.result().unwrap(), 2.333333333);
+        assert_close(calculator.eval(&parsed_input).unwrap(), 2.333333333);
     }
 
     #[allow(clippy::approx_constant)]
@@ -164,8 +659,7 @@ mod tests {
         let parsed_input = calc_parser::expr(input).unwrap();
         let mut calculator = Calculator::new();
         assert_eq!(calculator.define_variable(input, value), Ok(()));
-        calculator.visit_expr(&parsed_input);
-        assert_close(calculator.result().unwrap(), value);
+        assert_close(calculator.eval(&parsed_input).unwrap(), value);
     }
 
     #[test]
@@ -176,11 +670,61 @@ mod tests {
         let parsed_input = calc_parser::expr(&input).unwrap();
         let mut calculator = Calculator::new();
         assert_eq!(
-            calculator.define_function("mul", |args| args[0] * args[1]),
+            calculator.define_function("mul", Arity::Fixed(2), |args| Ok(args[0] * args[1])),
+            Ok(())
+        );
+        assert_close(calculator.eval(&parsed_input).unwrap(), a * b);
+    }
+
+    #[test]
+    fn closure_function() {
+        use std::cell::Cell;
+
+        let mut calculator = Calculator::new();
+        let table = [1.0, 10.0, 100.0];
+        assert_eq!(
+            calculator.define_function("lookup", Arity::Fixed(1), move |args| {
+                table
+                    .get(args[0] as usize)
+                    .copied()
+                    .ok_or(EvalError::Domain {
+                        name: "lookup".into(),
+                        value: args[0],
+                        span: Span::default(),
+                    })
+            }),
+            Ok(())
+        );
+        let counter = Rc::new(Cell::new(0.0));
+        let calls = counter.clone();
+        assert_eq!(
+            calculator.define_function("tick", Arity::Range(0, 1), move |args| {
+                calls.set(calls.get() + args.first().unwrap_or(&1.0));
+                Ok(calls.get())
+            }),
             Ok(())
         );
-        calculator.visit_expr(&parsed_input);
-        assert_close(calculator.result().unwrap(), a * b);
+        let mut eval = |input| calculator.eval(&calc_parser::expr(input).unwrap());
+        assert_eq!(eval("lookup(2) + lookup(1)"), Ok(110.0));
+        assert_eq!(
+            eval("lookup(3)"),
+            Err(EvalError::Domain {
+                name: "lookup".into(),
+                value: 3.0,
+                span: Span::new(0, 9),
+            })
+        );
+        assert_eq!(eval("tick() + tick(5)"), Ok(7.0));
+        assert_eq!(
+            eval("tick(1, 2)"),
+            Err(EvalError::ArityMismatch {
+                name: "tick".into(),
+                expected: Arity::Range(0, 1),
+                found: 2,
+                span: Span::new(0, 10),
+            })
+        );
+        assert_close(counter.get(), 6.0);
     }
 
     #[test]
@@ -190,7 +734,264 @@ mod tests {
         let parsed_input = calc_parser::expr(input).unwrap();
         let mut calculator = Calculator::new();
         assert_eq!(calculator.preset(), Ok(()));
-        calculator.visit_expr(&parsed_input);
-        assert_close(calculator.result().unwrap(), value);
+        assert_close(calculator.eval(&parsed_input).unwrap(), value);
+
+        for (input, value) in PRESET_CASES {
+            let parsed_input = calc_parser::expr(input).unwrap();
+            assert_same(input, calculator.eval(&parsed_input).unwrap(), *value);
+        }
+
+        // Every preset is documented
+        for (name, _) in calculator.variables() {
+            assert!(calculator.variable_doc(name).is_some(), "{name}");
+        }
+        let mut names: Vec<_> = calculator.variables().map(|(name, _)| name).collect();
+        names.sort_unstable();
+        let mut presets: Vec<_> = Calculator::preset_variables().collect();
+        presets.sort_unstable();
+        assert_eq!(names, presets);
+        for (name, _) in calculator.functions() {
+            assert!(calculator.function_doc(name).is_some(), "{name}");
+        }
+        assert_eq!(calculator.function_doc("sqrt"), Some("square root"));
+        assert!(calculator.describe_function("unknown", "").is_err());
+    }
+
+    #[test]
+    fn calc_power() {
+        let mut calculator = Calculator::new();
+        let mut eval = |input| calculator.eval(&calc_parser::expr(input).unwrap());
+        assert_eq!(eval("2^10"), Ok(1024.0));
+        assert_eq!(eval("2**3**2"), Ok(512.0));
+        assert_eq!(eval("-2^2"), Ok(-4.0));
+        assert_eq!(eval("(-2)^2"), Ok(4.0));
+        assert_eq!(eval("2^-1"), Ok(0.5));
+        assert_eq!(eval("3!^2"), Ok(36.0));
+        assert_eq!(eval("1 + 2 * 3^2"), Ok(19.0));
+    }
+
+    #[test]
+    fn calc_modulo() {
+        let mut calculator = Calculator::new();
+        for (input, value) in MODULO_CASES {
+            let parsed_input = calc_parser::expr(input).unwrap();
+            assert_close(calculator.eval(&parsed_input).unwrap(), *value);
+        }
+        let parsed_input = calc_parser::expr("1 % 0").unwrap();
+        assert!(calculator.eval(&parsed_input).unwrap().is_nan());
+    }
+
+    #[test]
+    fn calc_program() {
+        let mut calculator = Calculator::new();
+        assert_eq!(calculator.preset(), Ok(()));
+        let mut run = |input| calculator.run(&calc_parser::program(input).unwrap());
+        assert_eq!(run("let x = 2; y = x * 3\nx + y"), Ok(Some(8.0)));
+        // Bindings persist across runs
+        assert_eq!(run("x = x + 1; x"), Ok(Some(3.0)));
+        assert_eq!(run("z = 5"), Ok(Some(5.0)));
+        assert_eq!(run("let x = 1"), Err(EvalError::ReDefinition("x".into())));
+        assert_eq!(
+            run("w = 1; w + v"),
+            Err(EvalError::UnknownVariable {
+                name: "v".into(),
+                span: Span::new(11, 12),
+            })
+        );
+        assert_eq!(run("w"), Ok(Some(1.0)));
+    }
+
+    #[test]
+    fn user_function() {
+        let mut calculator = Calculator::new();
+        assert_eq!(calculator.preset(), Ok(()));
+        let mut run = |input| calculator.run(&calc_parser::program(input).unwrap());
+        assert_eq!(run("f(x, y) = x^2 + y"), Ok(None));
+        assert_eq!(run("f(3, 4)"), Ok(Some(13.0)));
+        // Parameters shadow globals, and callees cannot see the caller's parameters
+        assert_eq!(
+            run("x = 10; g(y) = x + y; h(x) = g(x) * 2; h(1)"),
+            Ok(Some(22.0))
+        );
+        assert_eq!(run("x"), Ok(Some(10.0)));
+        assert_eq!(
+            run("k(z) = y; k(1)"),
+            Err(EvalError::UnknownVariable {
+                name: "y".into(),
+                span: Span::new(7, 8),
+            })
+        );
+        // Parameters do not leak out of a failed call
+        assert_eq!(
+            run("z"),
+            Err(EvalError::UnknownVariable {
+                name: "z".into(),
+                span: Span::new(0, 1),
+            })
+        );
+        assert_eq!(run("f(x) = x + 1; f(1)"), Ok(Some(2.0)));
+        assert_eq!(
+            run("f(1, 2)"),
+            Err(EvalError::ArityMismatch {
+                name: "f".into(),
+                expected: Arity::Fixed(1),
+                found: 2,
+                span: Span::new(0, 7),
+            })
+        );
+        assert_eq!(
+            run("sin(x) = x"),
+            Err(EvalError::ReDefinition("sin".into()))
+        );
+        assert_eq!(
+            run("loop(x) = loop(x); loop(1)"),
+            Err(EvalError::RecursionLimit {
+                name: "loop".into(),
+                span: Span::new(10, 17),
+            })
+        );
+        assert_eq!(run("f(2)"), Ok(Some(3.0)));
+    }
+
+    #[test]
+    fn calc_factorial() {
+        let mut calculator = Calculator::new();
+        let mut eval = |input| calculator.eval(&calc_parser::expr(input).unwrap());
+        assert_eq!(eval("0!"), Ok(1.0));
+        assert_eq!(eval("5!"), Ok(120.0));
+        assert_eq!(eval("5.9!"), Ok(120.0));
+        assert_eq!(eval("171!"), Ok(f64::INFINITY));
+        assert_eq!(
+            eval("(-3)!"),
+            Err(EvalError::Domain {
+                name: "!".into(),
+                value: -3.0,
+                span: Span::new(0, 5),
+            })
+        );
+
+        for (input, value) in FACTORIAL_CASES {
+            match eval(input) {
+                Ok(result) => assert_same(input, result, *value),
+                Err(EvalError::Domain { .. }) => assert!(value.is_nan(), "{input}"),
+                Err(e) => panic!("`{input}` failed with {e}"),
+            }
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let mut calculator = Calculator::new();
+        assert_eq!(calculator.preset(), Ok(()));
+        let mut eval = |input| calculator.eval(&calc_parser::expr(input).unwrap());
+        assert_eq!(
+            eval("1 + foo"),
+            Err(EvalError::UnknownVariable {
+                name: "foo".into(),
+                span: Span::new(4, 7),
+            })
+        );
+        assert_eq!(
+            eval("foo(1)"),
+            Err(EvalError::UnknownFunction {
+                name: "foo".into(),
+                span: Span::new(0, 6),
+            })
+        );
+        assert_eq!(
+            eval("sin()"),
+            Err(EvalError::ArityMismatch {
+                name: "sin".into(),
+                expected: Arity::Fixed(1),
+                found: 0,
+                span: Span::new(0, 5),
+            })
+        );
+        assert_eq!(
+            eval("sin(1, 2)"),
+            Err(EvalError::ArityMismatch {
+                name: "sin".into(),
+                expected: Arity::Fixed(1),
+                found: 2,
+                span: Span::new(0, 9),
+            })
+        );
+        // A failed evaluation must not leave operands behind for the next one
+        assert_close(eval("sum(1, 2, 3)").unwrap(), 6.0);
+        assert_eq!(
+            calculator.define_variable("PI", 3.0),
+            Err(EvalError::ReDefinition("PI".into()))
+        );
+    }
+
+    #[test]
+    fn error_spans() {
+        let mut calculator = Calculator::new();
+        assert_eq!(calculator.preset(), Ok(()));
+        calculator
+            .define_function("fail", Arity::Variadic, |_| {
+                Err(EvalError::Domain {
+                    name: "fail".into(),
+                    value: 0.0,
+                    span: Span::default(),
+                })
+            })
+            .unwrap();
+        let mut span_of = |input| {
+            let program = calc_parser::program(input).unwrap();
+            let span = calculator.run(&program).unwrap_err().span().unwrap();
+            (span.start, span.end)
+        };
+        assert_eq!(span_of("1 + foo * 2"), (4, 7));
+        assert_eq!(span_of("sin(1) + bar(2)"), (9, 15));
+        assert_eq!(span_of("2 * (1 - 4)!"), (4, 12));
+        assert_eq!(span_of("x = 1\n1 + fail(x)"), (10, 17));
+        assert_eq!(span_of("f(x) = f(x)\n1 + f(2)"), (7, 11));
+    }
+
+    #[test]
+    fn failed_function() {
+        let mut calculator = Calculator::new();
+        let mut run = |input| {
+            let program = calc_parser::program(input).unwrap();
+            calculator.run(&program).unwrap_err();
+            calculator.failed_function().map(String::from)
+        };
+        assert_eq!(
+            run("g(x) = x + qqq; f(x) = g(x) * 2; f(1)"),
+            Some("g".into())
+        );
+        assert_eq!(run("f(qqq)"), None);
+        // The limit is hit by a call in the body of `h`
+        assert_eq!(run("h(x) = h(x); h(1)"), Some("h".into()));
+    }
+
+    #[test]
+    fn strict_mode() {
+        let mut calculator = Calculator::new();
+        let input = calc_parser::expr("1 + 2 / (1 - 1)").unwrap();
+        assert_eq!(calculator.eval(&input), Ok(f64::INFINITY));
+        calculator.set_strict(true);
+        let err = calculator.eval(&input).unwrap_err();
+        assert_eq!(
+            err,
+            EvalError::DivisionByZero {
+                span: Span::new(4, 15)
+            }
+        );
+        let span = err.span().unwrap();
+        assert_eq!((span.start, span.end), (4, 15));
+        for input in ["1 % 0", "1 // 0", "1 rem 0"] {
+            let parsed_input = calc_parser::expr(input).unwrap();
+            assert!(calculator.eval(&parsed_input).is_err());
+        }
+        let parsed_input = calc_parser::expr("0 / 1").unwrap();
+        assert_eq!(calculator.eval(&parsed_input), Ok(0.0));
+    }
+
+    #[test]
+    fn stack() {
+        let mut calculator = Calculator::new();
+        assert_eq!(calculator.result(), Err(EvalError::StackUnderflow));
     }
 }
@@ -1,110 +1,605 @@
-use super::Visitor;
-use crate::ast::{Atom, BinaryArithmetic, BinaryOp, Expr, FunctionCall, UnaryArithmetic, UnaryOp};
+use super::{Arity, ArityChecker, EvalError, Visitor};
+use crate::ast::{
+    Atom, BinaryArithmetic, BinaryOp, Expr, FunctionCall, FunctionDef, Span, Stmt, UnaryArithmetic,
+    UnaryOp,
+};
 use crate::symbols::{SymbolError, SymbolTable};
 
+use inkwell::attributes::{Attribute, AttributeLoc};
+use inkwell::basic_block::BasicBlock;
 use inkwell::builder::Builder;
 use inkwell::context::Context;
-use inkwell::execution_engine::{ExecutionEngine, JitFunction};
-use inkwell::module::Module;
-use inkwell::types::{BasicMetadataTypeEnum, FloatType};
-use inkwell::values::{BasicMetadataValueEnum, FloatValue, FunctionValue, PointerValue};
+use inkwell::execution_engine::{ExecutionEngine, JitFunction, UnsafeFunctionPointer};
+use inkwell::intrinsics::Intrinsic;
+use inkwell::module::{Linkage, Module};
+use inkwell::passes::PassBuilderOptions;
+use inkwell::targets::{
+    CodeModel, FileType, InitializationConfig, RelocMode, Target, TargetMachine,
+};
+use inkwell::types::{BasicMetadataTypeEnum, FloatType, FunctionType};
+use inkwell::values::{BasicMetadataValueEnum, FloatValue, FunctionValue, IntValue, PointerValue};
 use inkwell::AddressSpace;
-use inkwell::OptimizationLevel;
+use inkwell::{FloatPredicate, IntPredicate, OptimizationLevel};
 use std::f64::consts;
+use std::path::Path;
 
+// Entrypoints are named after these prefixes with a unique suffix
 pub type CalcMain = unsafe extern "C" fn() -> f64;
 pub const CALC_ENTRYPOINT: &str = "calc_main";
+/// Takes a pointer to the parameters, laid out in the order given to `compile_fn`
+pub type CalcFn = unsafe extern "C" fn(*const f64) -> f64;
+pub const CALC_FN_ENTRYPOINT: &str = "calc_fn";
+/// Takes the number of rows, one pointer per parameter column and the output column
+pub type CalcKernel = unsafe extern "C" fn(usize, *const *const f64, *mut f64);
+pub const CALC_KERNEL_ENTRYPOINT: &str = "calc_kernel";
+/// Private to each module, so every module builds its own copy.
+const FACTORIAL: &str = "rcalc.builtin.factorial";
 pub type FuncLLVM<'a, T> = fn(Vec<T>, &Builder<'a>) -> T;
 
+/// Variadic functions have no LLVM signature, calls to them are expanded
+/// inline into a fold over the arguments.
+#[derive(Debug, Clone, Copy)]
+enum Fold {
+    Sum,
+    Max,
+    Min,
+}
+
+/// Globals and functions are referred to by symbol, since they may live in a
+/// module compiled earlier and have to be declared again in the open one.
+#[derive(Debug, Clone)]
+enum Variable<'ctx> {
+    Global(String),
+    /// A parameter of the function being built
+    Local(PointerValue<'ctx>),
+}
+
+#[derive(Debug, Clone)]
+enum Callee {
+    Function { symbol: String, argc: usize },
+    Fold(Fold),
+}
+
+impl Callee {
+    fn arity(&self) -> Arity {
+        match self {
+            Callee::Function { argc, .. } => Arity::Fixed(*argc),
+            Callee::Fold(_) => Arity::Variadic,
+        }
+    }
+}
+
+/// A formula compiled by `CalculatorJIT::compile_fn`, ready to be called
+/// repeatedly with different arguments.
+#[derive(Debug)]
+pub struct CompiledFn<'a> {
+    function: JitFunction<'a, CalcFn>,
+    argc: usize,
+}
+
+impl CompiledFn<'_> {
+    pub fn argc(&self) -> usize {
+        self.argc
+    }
+
+    /// Evaluates the formula, `args` holds one value per parameter.
+    pub fn call(&self, args: &[f64]) -> f64 {
+        assert_eq!(
+            args.len(),
+            self.argc,
+            "Compiled function takes {}",
+            Arity::Fixed(self.argc)
+        );
+        // The function reads exactly `argc` values behind the pointer
+        unsafe { self.function.call(args.as_ptr()) }
+    }
+}
+
+/// A formula compiled by `CalculatorJIT::compile_kernel`, evaluated over
+/// whole columns of inputs at once.
+#[derive(Debug)]
+pub struct CompiledKernel<'a> {
+    function: JitFunction<'a, CalcKernel>,
+    columns: usize,
+}
+
+impl CompiledKernel<'_> {
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    /// Evaluates the formula for every row, `inputs` holds one column per
+    /// parameter and each column has as many rows as `out`.
+    pub fn call(&self, inputs: &[&[f64]], out: &mut [f64]) {
+        assert_eq!(
+            inputs.len(),
+            self.columns,
+            "Compiled kernel takes {} columns",
+            self.columns
+        );
+        assert!(
+            inputs.iter().all(|column| column.len() == out.len()),
+            "Every column must have {} rows",
+            out.len()
+        );
+        let columns: Vec<*const f64> = inputs.iter().map(|column| column.as_ptr()).collect();
+        // The kernel reads `out.len()` rows from each column and writes as many
+        unsafe {
+            self.function
+                .call(out.len(), columns.as_ptr(), out.as_mut_ptr())
+        }
+    }
+}
+
+/// Textual forms of the last compiled module, recorded on request.
+#[derive(Debug, Clone, Default)]
+pub struct Listing {
+    /// The IR as generated, before any pass ran
+    pub unoptimized: String,
+    /// The IR handed over to the execution engine
+    pub optimized: String,
+    pub assembly: String,
+}
+
+/// The names defined in a JIT session at some point, see
+/// `CalculatorJIT::checkpoint`.
+#[derive(Debug, Clone)]
+pub struct Checkpoint<'ctx> {
+    variables: SymbolTable<Variable<'ctx>>,
+    functions: SymbolTable<Callee>,
+}
+
+/// A JIT session. Definitions and code go into an open module, which every
+/// compilation hands over to the execution engine before opening a fresh one,
+/// so any number of expressions can be compiled one after another. Every
+/// symbol gets a unique suffix, which also keeps calculator names apart from
+/// the libm functions that intrinsics lower to.
 #[derive(Debug)]
 pub struct CalculatorJIT<'ctx> {
-    variables: SymbolTable<PointerValue<'ctx>>,
+    variables: SymbolTable<Variable<'ctx>>,
+    functions: SymbolTable<Callee>,
     context: &'ctx Context,
     module: Module<'ctx>,
     builder: Builder<'ctx>,
     execution_engine: ExecutionEngine<'ctx>,
+    /// Whether the open module defines symbols that later code may refer to
+    exports: bool,
+    /// The last compiled module when it only holds an entrypoint, freed by
+    /// the next compilation since the entrypoint cannot be called any more
+    disposable: Option<Module<'ctx>>,
+    next_id: usize,
+    opt_level: OptimizationLevel,
+    record_listing: bool,
+    listing: Option<Listing>,
 }
 
 impl<'ctx> CalculatorJIT<'ctx> {
     pub fn new(context: &'ctx Context) -> Self {
-        let module = context.create_module("calc");
-        let execution_engine = module
-            .create_jit_execution_engine(OptimizationLevel::None)
+        Self::with_opt_level(context, OptimizationLevel::None)
+    }
+
+    /// Creates a session running the optimization pipeline of `opt_level`
+    /// over every module, and generating machine code at that level.
+    pub fn with_opt_level(context: &'ctx Context, opt_level: OptimizationLevel) -> Self {
+        // The engine needs a module to start with, compiled modules are added later
+        let execution_engine = context
+            .create_module("calc")
+            .create_jit_execution_engine(opt_level)
             .unwrap();
         CalculatorJIT {
             variables: SymbolTable::new(),
+            functions: SymbolTable::new(),
             context,
-            module,
+            module: context.create_module("calc.0"),
             builder: context.create_builder(),
             execution_engine,
+            exports: false,
+            disposable: None,
+            next_id: 0,
+            opt_level,
+            record_listing: false,
+            listing: None,
+        }
+    }
+
+    /// Saves the names defined so far, see `restore`.
+    pub fn checkpoint(&self) -> Checkpoint<'ctx> {
+        Checkpoint {
+            variables: self.variables.clone(),
+            functions: self.functions.clone(),
         }
     }
 
+    /// Forgets the names defined since `checkpoint` was taken. Their code
+    /// stays in the execution engine, but later programs cannot refer to it.
+    pub fn restore(&mut self, checkpoint: &Checkpoint<'ctx>) {
+        self.variables = checkpoint.variables.clone();
+        self.functions = checkpoint.functions.clone();
+    }
+
+    /// Keeps the IR and assembly of every compiled module, see `listing`.
+    pub fn set_record_listing(&mut self, record: bool) {
+        self.record_listing = record;
+    }
+
+    /// Listing of the last compiled module, if recording was enabled.
+    pub fn listing(&self) -> Option<&Listing> {
+        self.listing.as_ref()
+    }
+
     #[inline]
     fn double(&self) -> FloatType<'ctx> {
         self.context.f64_type()
     }
 
+    fn unique_symbol(&mut self, name: &str) -> String {
+        self.next_id += 1;
+        format!("{name}.{}", self.next_id)
+    }
+
     pub fn define_variable(&mut self, name: &str, value: f64) -> Result<(), SymbolError> {
+        let symbol = self.unique_symbol(name);
+        self.variables
+            .define(name, Variable::Global(symbol.clone()))?;
+
         let var = self
             .module
-            .add_global(self.double(), Some(AddressSpace::default()), name);
-
+            .add_global(self.double(), Some(AddressSpace::default()), &symbol);
         let initial_value = self.double().const_float(value);
         var.set_initializer(&initial_value);
-
-        let alloca = var.as_pointer_value();
-        self.variables.define(name, alloca)?;
+        self.exports = true;
         Ok(())
     }
 
+    fn variable_pointer(&self, name: &str) -> Result<PointerValue<'ctx>, SymbolError> {
+        match self.variables.get(name)? {
+            Variable::Global(symbol) => Ok(self.declare_global(symbol)),
+            Variable::Local(pointer) => Ok(*pointer),
+        }
+    }
+
+    /// Returns the global `symbol` of the open module, declaring it when it
+    /// is defined by an earlier one.
+    fn declare_global(&self, symbol: &str) -> PointerValue<'ctx> {
+        self.module
+            .get_global(symbol)
+            .unwrap_or_else(|| {
+                self.module
+                    .add_global(self.double(), Some(AddressSpace::default()), symbol)
+            })
+            .as_pointer_value()
+    }
+
+    /// Returns the function `symbol` of the open module, declaring it when it
+    /// is defined by an earlier one.
+    fn declare_function(&self, symbol: &str, argc: usize) -> FunctionValue<'ctx> {
+        self.module.get_function(symbol).unwrap_or_else(|| {
+            self.module
+                .add_function(symbol, self.fn_type(argc), Some(Linkage::External))
+        })
+    }
+
+    /// Type of a function taking `argc` doubles and returning one.
+    fn fn_type(&self, argc: usize) -> FunctionType<'ctx> {
+        let args_types = std::iter::repeat_n(self.double(), argc)
+            .map(|f| f.into())
+            .collect::<Vec<BasicMetadataTypeEnum>>();
+        self.double().fn_type(args_types.as_slice(), false)
+    }
+
     fn get_variable(&mut self, name: &str) -> Result<FloatValue<'ctx>, SymbolError> {
-        let alloca = self.variables.get(name)?;
+        let pointer = self.variable_pointer(name)?;
         let var = self
             .builder
-            .build_load(self.double(), alloca, name)
+            .build_load(self.double(), pointer, name)
             .expect("Failed to load variable")
             .into_float_value();
 
         Ok(var)
     }
 
+    /// Stores `value` into the global backing `name`, creating the global first
+    /// when the statement declares a new binding or `name` is not bound yet.
+    fn store_variable(&mut self, name: &str, value: FloatValue<'ctx>, declare: bool) {
+        if declare || !self.variables.contains(name) {
+            self.define_variable(name, 0.0)
+                .expect("Variable is already defined");
+        }
+        let pointer = self.variable_pointer(name).unwrap();
+        self.builder
+            .build_store(pointer, value)
+            .expect("Failed to build store");
+    }
+
     pub fn define_function(
         &mut self,
         name: &str,
         argc: usize,
         func: FuncLLVM<'ctx, FloatValue<'ctx>>,
     ) -> Result<(), SymbolError> {
-        let ret_type = self.double();
-        let args_types = std::iter::repeat_n(ret_type, argc)
-            .map(|f| f.into())
-            .collect::<Vec<BasicMetadataTypeEnum>>();
-        let args_types = args_types.as_slice();
+        self.define_builtin(name, argc, |jit, args| func(args, &jit.builder))
+    }
+
+    /// Defines `name` as an LLVM function taking `argc` doubles, its body is
+    /// built by `build_body` from the parameters.
+    fn define_builtin<F>(
+        &mut self,
+        name: &str,
+        argc: usize,
+        build_body: F,
+    ) -> Result<(), SymbolError>
+    where
+        F: FnOnce(&Self, Vec<FloatValue<'ctx>>) -> FloatValue<'ctx>,
+    {
+        if self.functions.contains(name) {
+            return Err(SymbolError::ReDefinition);
+        }
+        let symbol = self.unique_symbol(name);
+        let fn_val = self.add_function(&symbol, argc);
+        let args = fn_val
+            .get_param_iter()
+            .map(|arg| arg.into_float_value())
+            .collect();
+
+        let ret_val = build_body(self, args);
+
+        self.builder
+            .build_return(Some(&ret_val))
+            .expect("Failed to build return");
+        self.exports = true;
+        self.functions
+            .define(name, Callee::Function { symbol, argc })
+    }
 
-        let fn_type = self.double().fn_type(args_types, false);
-        let fn_val = self.module.add_function(name, fn_type, None);
+    /// Defines `name` as a thin wrapper around an `llvm.*` intrinsic.
+    fn define_intrinsic(
+        &mut self,
+        name: &str,
+        intrinsic: &str,
+        argc: usize,
+    ) -> Result<(), SymbolError> {
+        self.define_builtin(name, argc, |jit, args| {
+            jit.build_intrinsic_call(intrinsic, &args)
+        })
+    }
+
+    fn define_fold(&mut self, name: &str, fold: Fold) -> Result<(), SymbolError> {
+        self.functions.define(name, Callee::Fold(fold))
+    }
+
+    /// Adds an empty function taking `argc` doubles to the open module and
+    /// moves the builder to its entry block.
+    fn add_function(&self, symbol: &str, argc: usize) -> FunctionValue<'ctx> {
+        let fn_val = self.module.add_function(symbol, self.fn_type(argc), None);
         let entry = self.context.append_basic_block(fn_val, "entry");
         self.builder.position_at_end(entry);
+        fn_val
+    }
 
-        let mut args = Vec::with_capacity(argc);
-        for i in 0..argc as u32 {
-            args.push(fn_val.get_nth_param(i).unwrap().into_float_value())
+    /// Lowers a function written in the expression language into an LLVM
+    /// function of the module. Parameters live in their own scope on top of
+    /// the globals, just like in the interpreter.
+    fn define_user_function(&mut self, def: &FunctionDef) {
+        if self.functions.contains(&def.name) {
+            panic!("Function `{}` is already defined", def.name);
         }
+        let caller_block = self.builder.get_insert_block();
 
-        let ret_val = func(args, &self.builder);
+        let symbol = self.unique_symbol(&def.name);
+        let argc = def.params.len();
+        let fn_val = self.add_function(&symbol, argc);
+        self.functions
+            .define(&def.name, Callee::Function { symbol, argc })
+            .unwrap();
+        self.exports = true;
 
+        self.variables.push_function_scope();
+        self.define_params(fn_val, &def.params);
+        let ret_val = self.visit_expr(&def.body);
         self.builder
             .build_return(Some(&ret_val))
             .expect("Failed to build return");
+        self.variables.pop_scope();
 
-        Ok(())
+        if let Some(block) = caller_block {
+            self.builder.position_at_end(block);
+        }
+    }
+
+    /// Binds the parameters of `fn_val` to `params` in the current scope,
+    /// each one copied into a stack slot at the insertion point.
+    fn define_params<S: AsRef<str>>(&mut self, fn_val: FunctionValue<'ctx>, params: &[S]) {
+        for (i, param) in params.iter().enumerate() {
+            let param = param.as_ref();
+            let value = fn_val.get_nth_param(i as u32).unwrap().into_float_value();
+            value.set_name(param);
+            let alloca = self
+                .builder
+                .build_alloca(self.double(), param)
+                .expect("Failed to build alloca");
+            self.builder
+                .build_store(alloca, value)
+                .expect("Failed to build store");
+            self.variables
+                .define(param, Variable::Local(alloca))
+                .expect("Duplicated parameter");
+        }
     }
 
-    pub fn get_function(&mut self, name: &str) -> Result<FunctionValue<'ctx>, SymbolError> {
-        match self.module.get_function(name) {
-            Some(func) => Ok(func),
-            None => Err(SymbolError::UnDefinition),
+    fn build_float_call(
+        &self,
+        func: FunctionValue<'ctx>,
+        args: &[FloatValue<'ctx>],
+    ) -> FloatValue<'ctx> {
+        let argsv: Vec<BasicMetadataValueEnum> = args.iter().map(|&val| val.into()).collect();
+
+        self.builder
+            .build_call(func, argsv.as_slice(), "tmp")
+            .expect("Unable to call function")
+            .try_as_basic_value()
+            .unwrap_basic()
+            .into_float_value()
+    }
+
+    fn build_intrinsic_call(&self, name: &str, args: &[FloatValue<'ctx>]) -> FloatValue<'ctx> {
+        let intrinsic = Intrinsic::find(name).expect("Unknown LLVM intrinsic");
+        let func = intrinsic
+            .get_declaration(&self.module, &[self.double().into()])
+            .expect("Failed to declare LLVM intrinsic");
+        self.build_float_call(func, args)
+    }
+
+    /// Returns the helper computing factorials, building it on first use. It
+    /// follows `factorial` in the interpreter, except that the inputs the
+    /// interpreter rejects give NaN, compiled code has no way to report errors.
+    fn factorial_function(&self) -> FunctionValue<'ctx> {
+        if let Some(func) = self.module.get_function(FACTORIAL) {
+            return func;
+        }
+        let caller_block = self.builder.get_insert_block();
+
+        let func = self
+            .module
+            .add_function(FACTORIAL, self.fn_type(1), Some(Linkage::Private));
+        let entry = self.context.append_basic_block(func, "entry");
+        let domain = self.context.append_basic_block(func, "domain");
+        let in_range = self.context.append_basic_block(func, "in_range");
+        let overflow = self.context.append_basic_block(func, "overflow");
+        let header = self.context.append_basic_block(func, "loop");
+        let body = self.context.append_basic_block(func, "body");
+        let exit = self.context.append_basic_block(func, "exit");
+        let n = func.get_nth_param(0).unwrap().into_float_value();
+        let one = self.double().const_float(1.0);
+
+        // Unordered comparison, so NaN is sent to the domain block as well
+        self.builder.position_at_end(entry);
+        let invalid = self.build_fcmp(FloatPredicate::ULT, n, self.double().const_zero());
+        self.build_branch(invalid, domain, in_range);
+
+        self.builder.position_at_end(domain);
+        self.builder
+            .build_return(Some(&self.double().const_float(f64::NAN)))
+            .expect("Failed to build return");
+
+        // 171! is the first factorial that overflows an f64
+        self.builder.position_at_end(in_range);
+        let limit = self.build_intrinsic_call("llvm.floor", &[n]);
+        let too_large = self.build_fcmp(FloatPredicate::OGE, n, self.double().const_float(171.0));
+        self.build_branch(too_large, overflow, header);
+
+        self.builder.position_at_end(overflow);
+        self.builder
+            .build_return(Some(&self.double().const_float(f64::INFINITY)))
+            .expect("Failed to build return");
+
+        // Multiply in the same order as the interpreter so results are identical
+        self.builder.position_at_end(header);
+        let i = self
+            .builder
+            .build_phi(self.double(), "i")
+            .expect("Failed to build phi");
+        let acc = self
+            .builder
+            .build_phi(self.double(), "acc")
+            .expect("Failed to build phi");
+        let i_value = i.as_basic_value().into_float_value();
+        let acc_value = acc.as_basic_value().into_float_value();
+        let more = self.build_fcmp(FloatPredicate::OLE, i_value, limit);
+        self.build_branch(more, body, exit);
+
+        self.builder.position_at_end(body);
+        let acc_next = self
+            .builder
+            .build_float_mul(acc_value, i_value, "acc_next")
+            .expect("Failed to build mul");
+        let i_next = self
+            .builder
+            .build_float_add(i_value, one, "i_next")
+            .expect("Failed to build add");
+        self.builder
+            .build_unconditional_branch(header)
+            .expect("Failed to build branch");
+
+        i.add_incoming(&[(&one, in_range), (&i_next, body)]);
+        acc.add_incoming(&[(&one, in_range), (&acc_next, body)]);
+
+        self.builder.position_at_end(exit);
+        self.builder
+            .build_return(Some(&acc_value))
+            .expect("Failed to build return");
+
+        if let Some(block) = caller_block {
+            self.builder.position_at_end(block);
         }
+        func
+    }
+
+    fn build_branch(
+        &self,
+        cond: IntValue<'ctx>,
+        then: BasicBlock<'ctx>,
+        otherwise: BasicBlock<'ctx>,
+    ) {
+        self.builder
+            .build_conditional_branch(cond, then, otherwise)
+            .expect("Failed to build branch");
+    }
+
+    fn build_fold(&self, fold: Fold, args: &[FloatValue<'ctx>]) -> FloatValue<'ctx> {
+        // Same starting points as the interpreter: `sum()` is 0, `max()` and `min()` are NaN
+        let init = match fold {
+            Fold::Sum => 0.0,
+            Fold::Max | Fold::Min => f64::NAN,
+        };
+        args.iter()
+            .fold(self.double().const_float(init), |acc, &arg| match fold {
+                Fold::Sum => self
+                    .builder
+                    .build_float_add(acc, arg, "sum")
+                    .expect("Failed to build add"),
+                // maxnum and minnum skip NaN operands, like f64::max and f64::min
+                Fold::Max => self.build_intrinsic_call("llvm.maxnum", &[acc, arg]),
+                Fold::Min => self.build_intrinsic_call("llvm.minnum", &[acc, arg]),
+            })
+    }
+
+    fn build_frem(&self, lhs: FloatValue<'ctx>, rhs: FloatValue<'ctx>) -> FloatValue<'ctx> {
+        self.builder
+            .build_float_rem(lhs, rhs, "rem")
+            .expect("Failed to build rem")
+    }
+
+    fn build_fcmp(
+        &self,
+        op: FloatPredicate,
+        lhs: FloatValue<'ctx>,
+        rhs: FloatValue<'ctx>,
+    ) -> IntValue<'ctx> {
+        self.builder
+            .build_float_compare(op, lhs, rhs, "cmp")
+            .expect("Failed to build compare")
+    }
+
+    fn build_float_select(
+        &self,
+        cond: IntValue<'ctx>,
+        then: FloatValue<'ctx>,
+        otherwise: FloatValue<'ctx>,
+    ) -> FloatValue<'ctx> {
+        self.builder
+            .build_select(cond, then, otherwise, "select")
+            .expect("Failed to build select")
+            .into_float_value()
+    }
+
+    /// Creates a checker that knows the signature of every function defined
+    /// so far.
+    pub fn arity_checker(&self) -> ArityChecker {
+        let mut checker = ArityChecker::new();
+        for (name, callee) in self.functions.iter() {
+            checker.define_function(name, callee.arity());
+        }
+        checker
     }
 
     pub fn preset(&mut self) -> Result<(), SymbolError> {
@@ -112,6 +607,16 @@ impl<'ctx> CalculatorJIT<'ctx> {
         self.define_variable("TAU", consts::TAU)?;
         self.define_variable("E", consts::E)?;
 
+        self.define_builtin("log", 2, |jit, args| {
+            let base = jit.build_intrinsic_call("llvm.log", &args[..1]);
+            let value = jit.build_intrinsic_call("llvm.log", &args[1..]);
+            jit.builder
+                .build_float_div(value, base, "log")
+                .expect("Failed to build div")
+        })?;
+        self.define_intrinsic("ln", "llvm.log", 1)?;
+        self.define_intrinsic("log_2", "llvm.log2", 1)?;
+        self.define_intrinsic("log_10", "llvm.log10", 1)?;
         self.define_function("add", 2, |args, builder| {
             let a = args[0];
             let b = args[1];
@@ -119,33 +624,554 @@ impl<'ctx> CalculatorJIT<'ctx> {
                 .build_float_add(a, b, "add")
                 .expect("Failed to build add")
         })?;
-        // Other ops is too complex to hand-write LLVM code...
+        self.define_fold("sum", Fold::Sum)?;
+        self.define_intrinsic("pow", "llvm.pow", 2)?;
+        self.define_intrinsic("sqrt", "llvm.sqrt", 1)?;
+        self.define_fold("max", Fold::Max)?;
+        self.define_fold("min", Fold::Min)?;
+        self.define_intrinsic("sin", "llvm.sin", 1)?;
+        self.define_intrinsic("cos", "llvm.cos", 1)?;
+        self.define_intrinsic("tan", "llvm.tan", 1)?;
+        self.define_intrinsic("floor", "llvm.floor", 1)?;
+        self.define_intrinsic("ceil", "llvm.ceil", 1)?;
+        self.define_intrinsic("abs", "llvm.fabs", 1)?;
         Ok(())
     }
 
     pub fn compile(&mut self, ast: &Expr) -> Option<JitFunction<'_, CalcMain>> {
-        let sig = self.double().fn_type(&[], false);
-        let func = self.module.add_function(CALC_ENTRYPOINT, sig, None);
+        self.compile_entrypoint(CALC_ENTRYPOINT, &[], |jit, _| jit.visit_expr(ast))
+    }
+
+    /// Compiles every statement of `program` into the entrypoint, which returns
+    /// the value of the last statement.
+    ///
+    /// Programs that cannot be compiled, see `check_program`, and calls with
+    /// the wrong number of arguments are reported without generating any code.
+    pub fn compile_program(
+        &mut self,
+        program: &[Stmt],
+    ) -> Result<JitFunction<'_, CalcMain>, Vec<EvalError>> {
+        let mut errors = self.check_program(program).err().unwrap_or_default();
+        if let Err(arity_errors) = self.arity_checker().check(program) {
+            errors.extend(arity_errors);
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        let calc_main = self.compile_entrypoint(CALC_ENTRYPOINT, &[], |jit, _| {
+            let mut ret = None;
+            for stmt in program {
+                let value = jit.visit_stmt(stmt);
+                if !matches!(stmt, Stmt::Function(_)) {
+                    ret = Some(value);
+                }
+            }
+            ret.unwrap_or_else(|| jit.double().const_float(f64::NAN))
+        });
+        Ok(calc_main.expect("Failed to compile the program"))
+    }
+
+    /// Compiles `ast` into a function of `params`, so that it can be evaluated
+    /// many times without recompiling. Identifiers listed in `params` are read
+    /// from the arguments and shadow variables of the same name, other
+    /// identifiers must be defined variables.
+    ///
+    /// Formulas that cannot be compiled, see `check_formula`, are reported
+    /// without generating any code.
+    pub fn compile_fn(
+        &mut self,
+        ast: &Expr,
+        params: &[&str],
+    ) -> Result<CompiledFn<'_>, Vec<EvalError>> {
+        self.check_formula(ast, params)?;
+        let args_type = self.context.ptr_type(AddressSpace::default());
+        let function = self
+            .compile_entrypoint(CALC_FN_ENTRYPOINT, &[args_type.into()], |jit, func| {
+                let args = func.get_nth_param(0).unwrap().into_pointer_value();
+                args.set_name("args");
+                jit.variables.push_function_scope();
+                for (i, param) in params.iter().enumerate() {
+                    let index = jit.context.i64_type().const_int(i as u64, false);
+                    let arg = unsafe {
+                        jit.builder
+                            .build_in_bounds_gep(jit.double(), args, &[index], param)
+                            .expect("Failed to build gep")
+                    };
+                    jit.variables
+                        .define(param, Variable::Local(arg))
+                        .expect("Duplicated parameter");
+                }
+                let ret = jit.visit_expr(ast);
+                jit.variables.pop_scope();
+                ret
+            })
+            .expect("Failed to compile the formula");
+        Ok(CompiledFn {
+            function,
+            argc: params.len(),
+        })
+    }
+
+    /// Compiles `ast` into a loop over columns of `params`, writing one result
+    /// per row. The module is optimized at `-O3` for the host CPU whatever
+    /// the level of the session, so that the loop gets vectorized. No
+    /// fast-math flags are set, NaN and infinities come out exactly as in the
+    /// interpreter.
+    ///
+    /// Formulas that cannot be compiled, see `check_formula`, are reported
+    /// without generating any code.
+    pub fn compile_kernel(
+        &mut self,
+        ast: &Expr,
+        params: &[&str],
+    ) -> Result<CompiledKernel<'_>, Vec<EvalError>> {
+        self.check_formula(ast, params)?;
+        let size_type = self
+            .context
+            .ptr_sized_int_type(self.execution_engine.get_target_data(), None);
+        let ptr_type = self.context.ptr_type(AddressSpace::default());
+        let fn_type = self
+            .context
+            .void_type()
+            .fn_type(&[size_type.into(), ptr_type.into(), ptr_type.into()], false);
+        let symbol = self.unique_symbol(CALC_KERNEL_ENTRYPOINT);
+        let func = self.module.add_function(&symbol, fn_type, None);
+        // The output never overlaps the inputs, this spares the vectorizer
+        // runtime alias checks
+        let noalias = self
+            .context
+            .create_enum_attribute(Attribute::get_named_enum_kind_id("noalias"), 0);
+        func.add_attribute(AttributeLoc::Param(2), noalias);
+
+        let rows = func.get_nth_param(0).unwrap().into_int_value();
+        let inputs = func.get_nth_param(1).unwrap().into_pointer_value();
+        let out = func.get_nth_param(2).unwrap().into_pointer_value();
+        rows.set_name("rows");
+        inputs.set_name("inputs");
+        out.set_name("out");
+
+        let entry = self.context.append_basic_block(func, "entry");
+        let header = self.context.append_basic_block(func, "loop");
+        let body = self.context.append_basic_block(func, "body");
+        let exit = self.context.append_basic_block(func, "exit");
+
+        self.builder.position_at_end(entry);
+        let mut columns = Vec::with_capacity(params.len());
+        for (i, param) in params.iter().enumerate() {
+            let index = size_type.const_int(i as u64, false);
+            let column = unsafe {
+                self.builder
+                    .build_in_bounds_gep(ptr_type, inputs, &[index], "column")
+                    .expect("Failed to build gep")
+            };
+            let column = self
+                .builder
+                .build_load(ptr_type, column, param)
+                .expect("Failed to load column")
+                .into_pointer_value();
+            columns.push(column);
+        }
+        self.builder
+            .build_unconditional_branch(header)
+            .expect("Failed to build branch");
+
+        self.builder.position_at_end(header);
+        let row = self
+            .builder
+            .build_phi(size_type, "row")
+            .expect("Failed to build phi");
+        let row_value = row.as_basic_value().into_int_value();
+        let more = self
+            .builder
+            .build_int_compare(IntPredicate::ULT, row_value, rows, "more")
+            .expect("Failed to build compare");
+        self.build_branch(more, body, exit);
+
+        self.builder.position_at_end(body);
+        self.variables.push_function_scope();
+        for (param, column) in params.iter().zip(columns) {
+            let arg = unsafe {
+                self.builder
+                    .build_in_bounds_gep(self.double(), column, &[row_value], param)
+                    .expect("Failed to build gep")
+            };
+            self.variables
+                .define(param, Variable::Local(arg))
+                .expect("Duplicated parameter");
+        }
+        let value = self.visit_expr(ast);
+        self.variables.pop_scope();
+        let slot = unsafe {
+            self.builder
+                .build_in_bounds_gep(self.double(), out, &[row_value], "slot")
+                .expect("Failed to build gep")
+        };
+        self.builder
+            .build_store(slot, value)
+            .expect("Failed to build store");
+        let next = self
+            .builder
+            .build_int_add(row_value, size_type.const_int(1, false), "next")
+            .expect("Failed to build add");
+        self.builder
+            .build_unconditional_branch(header)
+            .expect("Failed to build branch");
+        let body_end = self.builder.get_insert_block().unwrap();
+        row.add_incoming(&[(&size_type.const_zero(), entry), (&next, body_end)]);
+
+        self.builder.position_at_end(exit);
+        self.builder
+            .build_return(None)
+            .expect("Failed to build return");
+
+        self.submit_module(OptimizationLevel::Aggressive)
+            .expect("Failed to compile the kernel");
+        let function = unsafe { self.execution_engine.get_function(&symbol) }
+            .expect("Failed to compile the kernel");
+        Ok(CompiledKernel {
+            function,
+            columns: params.len(),
+        })
+    }
+
+    /// Reports every identifier of `ast` that is neither one of `params` nor a
+    /// defined variable, and every call to an undefined function. Code using
+    /// such names cannot be generated.
+    pub fn check_names(&self, ast: &Expr, params: &[&str]) -> Result<(), Vec<EvalError>> {
+        let mut errors = Vec::new();
+        self.collect_unknown_names(ast, params, &[], &mut errors);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Reports what would stop `ast` from being compiled as a function of
+    /// `params`: unknown names, calls with the wrong number of arguments and
+    /// parameters given more than once.
+    pub fn check_formula(&self, ast: &Expr, params: &[&str]) -> Result<(), Vec<EvalError>> {
+        let mut errors: Vec<EvalError> = params
+            .iter()
+            .enumerate()
+            .filter(|&(i, param)| params[..i].contains(param))
+            .map(|(_, param)| EvalError::ReDefinition(param.to_string()))
+            .collect();
+        if let Err(name_errors) = self.check_names(ast, params) {
+            errors.extend(name_errors);
+        }
+        if let Err(arity_errors) = self.arity_checker().check_expr(ast) {
+            errors.extend(arity_errors);
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Reports what would stop `program` from being compiled: unknown names,
+    /// names declared twice and recursive functions. Names bound by earlier
+    /// statements of the program count as defined.
+    pub fn check_program(&self, program: &[Stmt]) -> Result<(), Vec<EvalError>> {
+        let mut variables: Vec<&str> = Vec::new();
+        let mut functions: Vec<&str> = Vec::new();
+        let mut errors = Vec::new();
+        for stmt in program {
+            match stmt {
+                Stmt::Let(ref a) | Stmt::Assign(ref a) => {
+                    self.collect_unknown_names(&a.value, &variables, &functions, &mut errors);
+                    let defined =
+                        self.variables.contains(&a.name) || variables.contains(&a.name.as_str());
+                    if matches!(stmt, Stmt::Let(_)) && defined {
+                        errors.push(EvalError::ReDefinition(a.name.clone()));
+                    }
+                    variables.push(a.name.as_str());
+                }
+                Stmt::Function(ref def) => {
+                    if self.functions.contains(&def.name) || functions.contains(&def.name.as_str())
+                    {
+                        errors.push(EvalError::ReDefinition(def.name.clone()));
+                    }
+                    let scope: Vec<&str> = variables
+                        .iter()
+                        .copied()
+                        .chain(def.params.iter().map(String::as_str))
+                        .collect();
+                    let mut body_errors = Vec::new();
+                    self.collect_unknown_names(&def.body, &scope, &functions, &mut body_errors);
+                    errors.extend(body_errors.into_iter().map(|e| match e {
+                        EvalError::UnknownFunction { name, span } if name == def.name => {
+                            EvalError::UnsupportedRecursion { name, span }
+                        }
+                        e => e,
+                    }));
+                    functions.push(def.name.as_str());
+                }
+                Stmt::Expr(ref e) => {
+                    self.collect_unknown_names(e, &variables, &functions, &mut errors)
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// `variables` and `functions` are known on top of those of the session.
+    fn collect_unknown_names(
+        &self,
+        e: &Expr,
+        variables: &[&str],
+        functions: &[&str],
+        errors: &mut Vec<EvalError>,
+    ) {
+        match e {
+            Expr::UnaryArithmetic(ref u) => {
+                self.collect_unknown_names(&u.value, variables, functions, errors)
+            }
+            Expr::BinaryArithmetic(ref b) => {
+                self.collect_unknown_names(&b.lhs, variables, functions, errors);
+                self.collect_unknown_names(&b.rhs, variables, functions, errors);
+            }
+            Expr::FunctionCall(ref f) => {
+                if !self.functions.contains(&f.name) && !functions.contains(&f.name.as_str()) {
+                    errors.push(EvalError::UnknownFunction {
+                        name: f.name.clone(),
+                        span: f.span,
+                    });
+                }
+                for arg in &f.args {
+                    self.collect_unknown_names(arg, variables, functions, errors);
+                }
+            }
+            Expr::Atom(Atom::Ident(ref name), span) => {
+                if !variables.contains(&name.as_str()) && !self.variables.contains(name) {
+                    errors.push(EvalError::UnknownVariable {
+                        name: name.clone(),
+                        span: *span,
+                    });
+                }
+            }
+            Expr::Atom(Atom::Number(_), _) => {}
+        }
+    }
+
+    /// Writes `ast` as a function of `params` to the object file at `path`,
+    /// exported as `double name(double, ...)` with the C calling convention.
+    /// Code is position independent and targets the generic CPU of the host
+    /// architecture, so the object can be linked into a shared library and
+    /// shipped.
+    ///
+    /// The object has to be self-contained, so the session must not have
+    /// compiled anything yet: the builtins and variables it defines are
+    /// copied into the object as internal symbols.
+    pub fn write_object(
+        mut self,
+        ast: &Expr,
+        params: &[&str],
+        name: &str,
+        path: &Path,
+        opt_level: OptimizationLevel,
+    ) -> Result<(), String> {
+        let func =
+            self.module
+                .add_function(name, self.fn_type(params.len()), Some(Linkage::External));
+        let entry = self.context.append_basic_block(func, "entry");
+        self.builder.position_at_end(entry);
+        self.variables.push_function_scope();
+        self.define_params(func, params);
+        let ret = self.visit_expr(ast);
+        self.builder
+            .build_return(Some(&ret))
+            .expect("Failed to build return");
+        self.variables.pop_scope();
+
+        // Only `name` is exported, libm functions and intrinsics are left to
+        // the linker and LLVM
+        for function in self.module.get_functions() {
+            if function == func {
+                continue;
+            }
+            if function.count_basic_blocks() > 0 {
+                function.set_linkage(Linkage::Internal);
+            } else {
+                let symbol = function.get_name().to_string_lossy();
+                if symbol.contains('.') && !symbol.starts_with("llvm.") {
+                    return Err(format!("`{symbol}` was compiled into an earlier module"));
+                }
+            }
+        }
+        for global in self.module.get_globals() {
+            if global.is_declaration() {
+                let symbol = global.get_name().to_string_lossy();
+                return Err(format!("`{symbol}` was compiled into an earlier module"));
+            }
+            global.set_linkage(Linkage::Internal);
+        }
+
+        let machine = object_target_machine(opt_level)?;
+        self.module.set_triple(&machine.get_triple());
+        self.module
+            .set_data_layout(&machine.get_target_data().get_data_layout());
+        self.module.verify().map_err(|e| e.to_string())?;
+        optimize(&self.module, &machine, opt_level)?;
+        machine
+            .write_to_file(&self.module, FileType::Object, path)
+            .map_err(|e| e.to_string())
+    }
+
+    fn compile_entrypoint<T, F>(
+        &mut self,
+        name: &str,
+        params: &[BasicMetadataTypeEnum<'ctx>],
+        build_body: F,
+    ) -> Option<JitFunction<'_, T>>
+    where
+        T: UnsafeFunctionPointer,
+        F: FnOnce(&mut Self, FunctionValue<'ctx>) -> FloatValue<'ctx>,
+    {
+        let symbol = self.unique_symbol(name);
+        let sig = self.double().fn_type(params, false);
+        let func = self.module.add_function(&symbol, sig, None);
         let basic_block = self.context.append_basic_block(func, "entry");
 
         self.builder.position_at_end(basic_block);
 
-        let ret = self.visit_expr(ast);
+        let ret = build_body(self, func);
         self.builder
             .build_return(Some(&ret))
             .expect("Failed to build return");
 
-        unsafe { self.execution_engine.get_function(CALC_ENTRYPOINT).ok() }
+        self.submit_module(self.opt_level)?;
+        unsafe { self.execution_engine.get_function(&symbol).ok() }
     }
+
+    /// Optimizes the open module at `opt_level`, hands it over to the
+    /// execution engine and opens a fresh one for the next compilation.
+    fn submit_module(&mut self, opt_level: OptimizationLevel) -> Option<()> {
+        // Entrypoints borrow the session, so the previous one is gone by now
+        if let Some(module) = self.disposable.take() {
+            self.execution_engine.remove_module(&module).ok()?;
+        }
+        let name = format!("calc.{}", self.next_id);
+        let module = std::mem::replace(&mut self.module, self.context.create_module(&name));
+
+        if opt_level != OptimizationLevel::None || self.record_listing {
+            let machine = host_target_machine(opt_level).ok()?;
+            module.set_triple(&machine.get_triple());
+            module.set_data_layout(&machine.get_target_data().get_data_layout());
+            let unoptimized = self
+                .record_listing
+                .then(|| module.print_to_string().to_string());
+            optimize(&module, &machine, opt_level).ok()?;
+            if let Some(unoptimized) = unoptimized {
+                let assembly = machine
+                    .write_to_memory_buffer(&module, FileType::Assembly)
+                    .ok()?;
+                self.listing = Some(Listing {
+                    unoptimized,
+                    optimized: module.print_to_string().to_string(),
+                    assembly: String::from_utf8_lossy(assembly.as_slice()).into_owned(),
+                });
+            }
+        }
+
+        self.execution_engine.add_module(&module).ok()?;
+        // Modules with definitions stay in the engine for later code to link against
+        if !std::mem::take(&mut self.exports) {
+            self.disposable = Some(module);
+        }
+        Some(())
+    }
+}
+
+/// A target machine for the host CPU, generating code at `opt_level`.
+fn host_target_machine(opt_level: OptimizationLevel) -> Result<TargetMachine, String> {
+    native_target_machine(
+        &TargetMachine::get_host_cpu_name().to_string(),
+        &TargetMachine::get_host_cpu_features().to_string(),
+        opt_level,
+        RelocMode::Default,
+        CodeModel::JITDefault,
+    )
+}
+
+/// A target machine for object files that run on any CPU of the host
+/// architecture, with position independent code.
+fn object_target_machine(opt_level: OptimizationLevel) -> Result<TargetMachine, String> {
+    native_target_machine("generic", "", opt_level, RelocMode::PIC, CodeModel::Default)
+}
+
+fn native_target_machine(
+    cpu: &str,
+    features: &str,
+    opt_level: OptimizationLevel,
+    reloc_mode: RelocMode,
+    code_model: CodeModel,
+) -> Result<TargetMachine, String> {
+    Target::initialize_native(&InitializationConfig::default())?;
+    let triple = TargetMachine::get_default_triple();
+    let target = Target::from_triple(&triple).map_err(|e| e.to_string())?;
+    target
+        .create_target_machine(&triple, cpu, features, opt_level, reloc_mode, code_model)
+        .ok_or_else(|| "Failed to create target machine".into())
+}
+
+/// Runs the default pipeline of `opt_level` over `module`, the loop and SLP
+/// vectorizers are enabled from `-O2` on like in clang.
+fn optimize(
+    module: &Module,
+    machine: &TargetMachine,
+    opt_level: OptimizationLevel,
+) -> Result<(), String> {
+    let passes = match opt_level {
+        OptimizationLevel::None => "default<O0>",
+        OptimizationLevel::Less => "default<O1>",
+        OptimizationLevel::Default => "default<O2>",
+        OptimizationLevel::Aggressive => "default<O3>",
+    };
+    let vectorize = matches!(
+        opt_level,
+        OptimizationLevel::Default | OptimizationLevel::Aggressive
+    );
+    let options = PassBuilderOptions::create();
+    options.set_loop_vectorization(vectorize);
+    options.set_loop_slp_vectorization(vectorize);
+    module
+        .run_passes(passes, machine, options)
+        .map_err(|e| e.to_string())
 }
 
 impl<'ctx> Visitor<FloatValue<'ctx>> for CalculatorJIT<'ctx> {
+    fn visit_stmt(&mut self, s: &Stmt) -> FloatValue<'ctx> {
+        match s {
+            Stmt::Let(ref a) => {
+                let value = self.visit_expr(&a.value);
+                self.store_variable(&a.name, value, true);
+                value
+            }
+            Stmt::Assign(ref a) => {
+                let value = self.visit_expr(&a.value);
+                self.store_variable(&a.name, value, false);
+                value
+            }
+            Stmt::Function(ref def) => {
+                self.define_user_function(def);
+                self.double().const_float(f64::NAN)
+            }
+            Stmt::Expr(ref e) => self.visit_expr(e),
+        }
+    }
+
     fn visit_expr(&mut self, e: &Expr) -> FloatValue<'ctx> {
         match e {
             Expr::UnaryArithmetic(ref u) => self.visit_unary(u),
             Expr::BinaryArithmetic(ref b) => self.visit_binary(b),
             Expr::FunctionCall(ref f) => self.visit_function(f),
-            Expr::Atom(ref a) => self.visit_atom(a),
+            Expr::Atom(ref a, span) => self.visit_atom(a, *span),
         }
     }
     fn visit_unary(&mut self, u: &UnaryArithmetic) -> FloatValue<'ctx> {
@@ -157,7 +1183,10 @@ impl<'ctx> Visitor<FloatValue<'ctx>> for CalculatorJIT<'ctx> {
                 .builder
                 .build_float_neg(value, "neg")
                 .expect("Failed to build neg"),
-            UnaryOp::Fac => unimplemented!(),
+            UnaryOp::Fac => {
+                let func = self.factorial_function();
+                self.build_float_call(func, &[value])
+            }
         }
     }
 
@@ -181,32 +1210,75 @@ impl<'ctx> Visitor<FloatValue<'ctx>> for CalculatorJIT<'ctx> {
                 .builder
                 .build_float_div(lhs, rhs, "div")
                 .expect("Failed to build div"),
+            BinaryOp::Mod => {
+                // frem truncates, move a non-zero remainder over to the divisor's sign
+                let rem = self.build_frem(lhs, rhs);
+                let zero = self.double().const_zero();
+                let rem_nonzero = self.build_fcmp(FloatPredicate::ONE, rem, zero);
+                let rem_neg = self.build_fcmp(FloatPredicate::OLT, rem, zero);
+                let rhs_neg = self.build_fcmp(FloatPredicate::OLT, rhs, zero);
+                let sign_differs = self
+                    .builder
+                    .build_xor(rem_neg, rhs_neg, "sign_differs")
+                    .expect("Failed to build xor");
+                let needs_fixup = self
+                    .builder
+                    .build_and(rem_nonzero, sign_differs, "needs_fixup")
+                    .expect("Failed to build and");
+                let fixed = self
+                    .builder
+                    .build_float_add(rem, rhs, "mod_fixup")
+                    .expect("Failed to build add");
+                self.build_float_select(needs_fixup, fixed, rem)
+            }
+            BinaryOp::FloorDiv => {
+                let quotient = self
+                    .builder
+                    .build_float_div(lhs, rhs, "div")
+                    .expect("Failed to build div");
+                self.build_intrinsic_call("llvm.floor", &[quotient])
+            }
+            BinaryOp::Rem => {
+                // frem truncates, a negative remainder is shifted by |rhs|
+                let rem = self.build_frem(lhs, rhs);
+                let zero = self.double().const_zero();
+                let rem_neg = self.build_fcmp(FloatPredicate::OLT, rem, zero);
+                let rhs_abs = self.build_intrinsic_call("llvm.fabs", &[rhs]);
+                let fixed = self
+                    .builder
+                    .build_float_add(rem, rhs_abs, "rem_fixup")
+                    .expect("Failed to build add");
+                self.build_float_select(rem_neg, fixed, rem)
+            }
+            BinaryOp::Pow => self.build_intrinsic_call("llvm.pow", &[lhs, rhs]),
         }
     }
 
     fn visit_function(&mut self, f: &FunctionCall) -> FloatValue<'ctx> {
         let argc = f.args.len();
-        let func = self.get_function(&f.name).unwrap();
-        let mut argv = Vec::with_capacity(argc);
-
-        for i in 0..argc {
-            argv.push(self.visit_expr(&f.args[i]))
+        let callee = self.functions.get(&f.name).unwrap().clone();
+        let arity = callee.arity();
+        if !arity.accepts(argc) {
+            panic!("Function `{}` takes {arity} but {argc} were given", f.name);
+        }
+        match callee {
+            Callee::Function { symbol, argc } => {
+                let func = self.declare_function(&symbol, argc);
+                let caller = self.builder.get_insert_block().and_then(|b| b.get_parent());
+                if caller == Some(func) {
+                    panic!("Recursive call to `{}` is not supported", f.name);
+                }
+                let argv: Vec<_> = f.args.iter().map(|arg| self.visit_expr(arg)).collect();
+                self.build_float_call(func, &argv)
+            }
+            Callee::Fold(fold) => {
+                let argv: Vec<_> = f.args.iter().map(|arg| self.visit_expr(arg)).collect();
+                self.build_fold(fold, &argv)
+            }
         }
-
-        let argsv: Vec<BasicMetadataValueEnum> =
-            argv.iter().by_ref().map(|&val| val.into()).collect();
-
-        let ret_val = self
-            .builder
-            .build_call(func, argsv.as_slice(), "tmp")
-            .expect("Unable to call function")
-            .try_as_basic_value()
-            .unwrap_basic();
-
-        ret_val.into_float_value()
     }
 
-    fn visit_atom(&mut self, a: &Atom) -> FloatValue<'ctx> {
+    fn visit_atom(&mut self, a: &Atom, _span: Span) -> FloatValue<'ctx> {
         match a {
             Atom::Ident(ref id) => self.get_variable(id).unwrap(),
             Atom::Number(ref n) => self.double().const_float(*n),
@@ -218,7 +1290,8 @@ impl<'ctx> Visitor<FloatValue<'ctx>> for CalculatorJIT<'ctx> {
 mod tests {
     use super::*;
     use crate::parser::calc_parser;
-    use crate::utils::assert_close;
+    use crate::utils::{assert_close, assert_same, FACTORIAL_CASES, MODULO_CASES, PRESET_CASES};
+    use crate::visitor::{Calculator, EvalError};
 
     #[test]
     fn calc_number() {
@@ -268,6 +1341,57 @@ mod tests {
         assert_close(result, a * b);
     }
 
+    #[test]
+    fn calc_power() {
+        let context = Context::create();
+        for (input, value) in [
+            ("2^10", 1024.0),
+            ("2**3**2", 512.0),
+            ("-2^2", -4.0),
+            ("2^-1", 0.5),
+        ] {
+            let parsed_input = calc_parser::expr(input).unwrap();
+            let mut calculator_jit = CalculatorJIT::new(&context);
+            let calc_main = calculator_jit.compile(&parsed_input).unwrap();
+            let result = unsafe { calc_main.call() };
+            assert_close(result, value);
+        }
+    }
+
+    #[test]
+    fn calc_modulo() {
+        let context = Context::create();
+        for (input, value) in MODULO_CASES {
+            let parsed_input = calc_parser::expr(input).unwrap();
+            let mut calculator_jit = CalculatorJIT::new(&context);
+            let calc_main = calculator_jit.compile(&parsed_input).unwrap();
+            let result = unsafe { calc_main.call() };
+            assert_close(result, *value);
+        }
+    }
+
+    #[test]
+    fn calc_program() {
+        let input = "let x = 2; y = x * 3\nx = x + y; x";
+        let parsed_input = calc_parser::program(input).unwrap();
+        let context = Context::create();
+        let mut calculator_jit = CalculatorJIT::new(&context);
+        let calc_main = calculator_jit.compile_program(&parsed_input).unwrap();
+        let result = unsafe { calc_main.call() };
+        assert_close(result, 8.0);
+    }
+
+    #[test]
+    fn user_function() {
+        let input = "x = 10; f(x, y) = x^2 + y; g(y) = x + y; h(x) = g(x) * 2; f(3, 4) + h(1)";
+        let parsed_input = calc_parser::program(input).unwrap();
+        let context = Context::create();
+        let mut calculator_jit = CalculatorJIT::new(&context);
+        let calc_main = calculator_jit.compile_program(&parsed_input).unwrap();
+        let result = unsafe { calc_main.call() };
+        assert_close(result, 13.0 + 22.0);
+    }
+
     #[test]
     fn calc_preset() {
         let input = "1 - add(PI * E, TAU)";
@@ -280,4 +1404,319 @@ mod tests {
         let result = unsafe { calc_main.call() };
         assert_close(result, value);
     }
+
+    #[test]
+    fn preset_parity() {
+        let context = Context::create();
+        let mut calculator = Calculator::new();
+        calculator.preset().unwrap();
+        for (input, value) in PRESET_CASES {
+            let parsed_input = calc_parser::expr(input).unwrap();
+            let mut calculator_jit = CalculatorJIT::new(&context);
+            calculator_jit.preset().unwrap();
+            let calc_main = calculator_jit.compile(&parsed_input).unwrap();
+            let result = unsafe { calc_main.call() };
+            assert_same(input, result, *value);
+            assert_same(input, result, calculator.eval(&parsed_input).unwrap());
+        }
+    }
+
+    #[test]
+    fn calc_factorial() {
+        let context = Context::create();
+        let mut calculator = Calculator::new();
+        for (input, value) in FACTORIAL_CASES {
+            let parsed_input = calc_parser::expr(input).unwrap();
+            let mut calculator_jit = CalculatorJIT::new(&context);
+            let calc_main = calculator_jit.compile(&parsed_input).unwrap();
+            let result = unsafe { calc_main.call() };
+            assert_same(input, result, *value);
+            match calculator.eval(&parsed_input) {
+                Ok(value) => assert_same(input, result, value),
+                Err(EvalError::Domain { .. }) => assert!(result.is_nan(), "{input}"),
+                Err(e) => panic!("`{input}` failed with {e}"),
+            }
+        }
+    }
+
+    #[test]
+    fn compile_fn() {
+        let input = "x^2 + sin(x) * y - PI";
+        let parsed_input = calc_parser::expr(input).unwrap();
+        let context = Context::create();
+        let mut calculator_jit = CalculatorJIT::new(&context);
+        calculator_jit.preset().unwrap();
+        // A parameter shadows the variable of the same name
+        calculator_jit.define_variable("x", 100.0).unwrap();
+        let formula = calculator_jit
+            .compile_fn(&parsed_input, &["x", "y"])
+            .unwrap();
+        assert_eq!(formula.argc(), 2);
+        for i in 0..1000 {
+            let (x, y) = (i as f64 / 10.0, 1.0 - i as f64);
+            let value = x.powi(2) + x.sin() * y - consts::PI;
+            assert_close(formula.call(&[x, y]), value);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "takes 1 argument")]
+    fn compile_fn_argc() {
+        let parsed_input = calc_parser::expr("x + 1").unwrap();
+        let context = Context::create();
+        let mut calculator_jit = CalculatorJIT::new(&context);
+        let formula = calculator_jit.compile_fn(&parsed_input, &["x"]).unwrap();
+        formula.call(&[1.0, 2.0]);
+    }
+
+    #[test]
+    fn compile_fn_errors() {
+        let context = Context::create();
+        let mut calculator_jit = CalculatorJIT::new(&context);
+        calculator_jit.preset().unwrap();
+        for (input, params) in [
+            ("x + z", &["x"][..]),
+            ("f(x)", &["x"]),
+            ("sqrt(x, 2)", &["x"]),
+            ("x * y", &["x", "y", "x"]),
+        ] {
+            let parsed_input = calc_parser::expr(input).unwrap();
+            let compiled = calculator_jit.compile_fn(&parsed_input, params);
+            assert!(compiled.is_err(), "{input}");
+        }
+        let parsed_input = calc_parser::expr("sqrt(x) + PI").unwrap();
+        let formula = calculator_jit.compile_fn(&parsed_input, &["x"]).unwrap();
+        assert_close(formula.call(&[4.0]), 2.0 + consts::PI);
+    }
+
+    #[test]
+    fn compile_kernel() {
+        let input = "x / y + sqrt(x) - max(x, y) * 2^y + x % y";
+        let parsed_input = calc_parser::expr(input).unwrap();
+        let context = Context::create();
+        let mut calculator_jit = CalculatorJIT::new(&context);
+        calculator_jit.preset().unwrap();
+        let kernel = calculator_jit
+            .compile_kernel(&parsed_input, &["x", "y"])
+            .unwrap();
+        assert_eq!(kernel.columns(), 2);
+
+        // Every pair of special values, then enough rows to leave a remainder
+        // after the vectorized part of the loop
+        let special = [
+            0.0,
+            -0.0,
+            1.0,
+            -2.5,
+            1e308,
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            f64::NAN,
+        ];
+        let (mut xs, mut ys): (Vec<f64>, Vec<f64>) = special
+            .iter()
+            .flat_map(|&x| special.iter().map(move |&y| (x, y)))
+            .unzip();
+        xs.extend((0..1003).map(|i| i as f64 * 0.37 - 100.0));
+        ys.extend((0..1003).map(|i| (i % 17) as f64 - 8.0));
+
+        let mut out = vec![0.0; xs.len()];
+        kernel.call(&[&xs, &ys], &mut out);
+
+        let mut calculator = Calculator::new();
+        calculator.preset().unwrap();
+        for ((&x, &y), &result) in xs.iter().zip(&ys).zip(&out) {
+            calculator.assign_variable("x", x).unwrap();
+            calculator.assign_variable("y", y).unwrap();
+            let value = calculator.eval(&parsed_input).unwrap();
+            assert_same(&format!("{input} with x = {x}, y = {y}"), result, value);
+        }
+
+        let empty: &[f64] = &[];
+        kernel.call(&[empty, empty], &mut []);
+    }
+
+    #[test]
+    fn compile_kernel_errors() {
+        let context = Context::create();
+        let mut calculator_jit = CalculatorJIT::new(&context);
+        calculator_jit.preset().unwrap();
+        for (input, params) in [
+            ("x + z", &["x"][..]),
+            ("max(x) + g(x)", &["x"]),
+            ("pow(x)", &["x"]),
+            ("x", &["x", "x"]),
+        ] {
+            let parsed_input = calc_parser::expr(input).unwrap();
+            let compiled = calculator_jit.compile_kernel(&parsed_input, params);
+            assert!(compiled.is_err(), "{input}");
+        }
+        let parsed_input = calc_parser::expr("x * 2").unwrap();
+        let kernel = calculator_jit
+            .compile_kernel(&parsed_input, &["x"])
+            .unwrap();
+        let mut out = [0.0; 2];
+        kernel.call(&[&[1.0, 2.5]], &mut out);
+        assert_eq!(out, [2.0, 5.0]);
+    }
+
+    #[test]
+    fn session() {
+        let context = Context::create();
+        let mut calculator_jit = CalculatorJIT::new(&context);
+        calculator_jit.preset().unwrap();
+
+        // Definitions made by one compilation are visible to the next ones
+        let program = calc_parser::program("let x = 3; f(y) = x * y + sqrt(y)").unwrap();
+        calculator_jit.compile_program(&program).unwrap();
+        for (input, value) in [("f(4)", 14.0), ("x!", 6.0), ("sum(x, f(1))", 7.0)] {
+            let parsed_input = calc_parser::expr(input).unwrap();
+            let calc_main = calculator_jit.compile(&parsed_input).unwrap();
+            assert_close(unsafe { calc_main.call() }, value);
+        }
+        let program = calc_parser::program("x = f(x); x").unwrap();
+        let calc_main = calculator_jit.compile_program(&program).unwrap();
+        assert_close(unsafe { calc_main.call() }, 9.0 + f64::sqrt(3.0));
+
+        let parsed_input = calc_parser::expr("y - x").unwrap();
+        let formula = calculator_jit.compile_fn(&parsed_input, &["y"]).unwrap();
+        assert_close(formula.call(&[10.0]), 1.0 - f64::sqrt(3.0));
+    }
+
+    #[test]
+    fn session_frees_modules() {
+        let context = Context::create();
+        let mut calculator_jit = CalculatorJIT::new(&context);
+        calculator_jit.define_variable("x", 2.0).unwrap();
+        for i in 0..1000 {
+            let parsed_input = calc_parser::expr(&format!("x * {i}")).unwrap();
+            let calc_main = calculator_jit.compile(&parsed_input).unwrap();
+            assert_close(unsafe { calc_main.call() }, 2.0 * i as f64);
+        }
+        // Only the module of the last entrypoint is still waiting to be freed
+        assert!(calculator_jit.disposable.is_some());
+        assert!(!calculator_jit.exports);
+    }
+
+    #[test]
+    fn listing() {
+        let parsed_input = calc_parser::expr("sqrt(4) + x").unwrap();
+        let context = Context::create();
+        for (opt_level, folded) in [
+            (OptimizationLevel::None, false),
+            (OptimizationLevel::Default, true),
+        ] {
+            let mut calculator_jit = CalculatorJIT::with_opt_level(&context, opt_level);
+            calculator_jit.preset().unwrap();
+            calculator_jit.define_variable("x", 1.0).unwrap();
+            assert!(calculator_jit.listing().is_none());
+            calculator_jit.set_record_listing(true);
+            let calc_main = calculator_jit.compile(&parsed_input).unwrap();
+            assert_close(unsafe { calc_main.call() }, 3.0);
+
+            let listing = calculator_jit.listing().unwrap();
+            assert!(listing.unoptimized.contains("call double @sqrt."));
+            // `sqrt` is inlined and `sqrt(4)` folded into a constant
+            assert_eq!(listing.optimized.contains("2.000000e+00"), folded);
+            assert_eq!(listing.optimized.contains("call double @sqrt."), !folded);
+            assert!(!listing.assembly.is_empty());
+        }
+    }
+
+    #[test]
+    fn preset_arity() {
+        let context = Context::create();
+        let mut calculator_jit = CalculatorJIT::new(&context);
+        calculator_jit.preset().unwrap();
+        let program = calc_parser::program("sum(1, 2, 3) + max(1) + sqrt(1, 2)").unwrap();
+        let errors = calculator_jit.arity_checker().check(&program).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(&errors[0], EvalError::ArityMismatch { name, .. } if name == "sqrt"));
+    }
+
+    #[test]
+    fn check_program() {
+        let context = Context::create();
+        let mut calculator_jit = CalculatorJIT::new(&context);
+        calculator_jit.preset().unwrap();
+        let check = |input: &str| {
+            let program = calc_parser::program(input).unwrap();
+            calculator_jit.check_program(&program)
+        };
+        assert_eq!(
+            check("let x = 1; f(y) = x * y + PI; x = f(x); sqrt(x)"),
+            Ok(())
+        );
+
+        let messages = |input: &str| -> Vec<String> {
+            check(input)
+                .unwrap_err()
+                .iter()
+                .map(|e| e.to_string())
+                .collect()
+        };
+        assert_eq!(
+            messages("y + g(1); f(a) = a + b; let y = 1"),
+            [
+                "unknown variable `y`",
+                "unknown function `g`",
+                "unknown variable `b`"
+            ]
+        );
+        assert_eq!(
+            messages("let x = 1; let x = 2; let PI = 3; f(a) = a; f(b) = b"),
+            [
+                "`x` is already defined",
+                "`PI` is already defined",
+                "`f` is already defined"
+            ]
+        );
+        assert_eq!(
+            messages("fac(n) = n * fac(n - 1)"),
+            ["recursive call to `fac` is not supported by the JIT backend"]
+        );
+    }
+
+    #[test]
+    fn compile_program_errors() {
+        let context = Context::create();
+        let mut calculator_jit = CalculatorJIT::new(&context);
+        calculator_jit.preset().unwrap();
+        for input in [
+            "let PI = 1",
+            "let x = 1; let x = 2",
+            "x + 1",
+            "f(x) = x; f(y) = y",
+            "g(x) = g(x - 1)",
+            "sqrt(1, 2)",
+            "f(x) = x; f(1, 2)",
+        ] {
+            let program = calc_parser::program(input).unwrap();
+            assert!(calculator_jit.compile_program(&program).is_err(), "{input}");
+        }
+        // Nothing of a rejected program is defined
+        let program = calc_parser::program("let x = 2; x * 3").unwrap();
+        let calc_main = calculator_jit.compile_program(&program).unwrap();
+        assert_close(unsafe { calc_main.call() }, 6.0);
+    }
+
+    #[test]
+    fn restore_checkpoint() {
+        let context = Context::create();
+        let mut calculator_jit = CalculatorJIT::new(&context);
+        calculator_jit.preset().unwrap();
+        let checkpoint = calculator_jit.checkpoint();
+        for input in [
+            "let x = 2; f(y) = x * y; f(3)",
+            "let x = 3; f(y) = x + y; f(3)",
+        ] {
+            calculator_jit.restore(&checkpoint);
+            let program = calc_parser::program(input).unwrap();
+            let calc_main = calculator_jit.compile_program(&program).unwrap();
+            assert_close(unsafe { calc_main.call() }, 6.0);
+        }
+        calculator_jit.restore(&checkpoint);
+        let program = calc_parser::program("f(x)").unwrap();
+        assert!(calculator_jit.compile_program(&program).is_err());
+    }
 }
@@ -0,0 +1,149 @@
+use super::Visitor;
+use crate::ast::{
+    Atom, BinaryArithmetic, BinaryOp, Expr, FunctionCall, Span, Stmt, UnaryArithmetic, UnaryOp,
+};
+use serde_json::{json, Value};
+
+/// Turns the syntax tree into JSON, each node is an object with its `kind`
+/// and its `span` as byte offsets into the source:
+///
+/// ```json
+/// {"kind": "Binary", "op": "Add", "span": {"start": 0, "end": 5}, "lhs": ..., "rhs": ...}
+/// ```
+#[derive(Debug, Default)]
+pub struct JsonPrinter;
+
+impl JsonPrinter {
+    pub fn new() -> Self {
+        JsonPrinter
+    }
+
+    /// An array with one node per statement.
+    pub fn program(&mut self, program: &[Stmt]) -> Value {
+        Value::Array(program.iter().map(|stmt| self.visit_stmt(stmt)).collect())
+    }
+}
+
+fn span(span: Span) -> Value {
+    json!({"start": span.start, "end": span.end})
+}
+
+impl Visitor<Value> for JsonPrinter {
+    fn visit_stmt(&mut self, s: &Stmt) -> Value {
+        match s {
+            Stmt::Let(ref a) | Stmt::Assign(ref a) => json!({
+                "kind": if matches!(s, Stmt::Let(_)) { "Let" } else { "Assign" },
+                "span": span(a.span),
+                "name": a.name,
+                "value": self.visit_expr(&a.value),
+            }),
+            Stmt::Function(ref def) => json!({
+                "kind": "Function",
+                "span": span(def.span),
+                "name": def.name,
+                "params": def.params,
+                "body": self.visit_expr(&def.body),
+            }),
+            Stmt::Expr(ref e) => json!({
+                "kind": "Expr",
+                "span": span(e.span()),
+                "expr": self.visit_expr(e),
+            }),
+        }
+    }
+
+    fn visit_expr(&mut self, e: &Expr) -> Value {
+        match e {
+            Expr::UnaryArithmetic(ref u) => self.visit_unary(u),
+            Expr::BinaryArithmetic(ref b) => self.visit_binary(b),
+            Expr::FunctionCall(ref f) => self.visit_function(f),
+            Expr::Atom(ref a, span) => self.visit_atom(a, *span),
+        }
+    }
+
+    fn visit_unary(&mut self, u: &UnaryArithmetic) -> Value {
+        let op = match u.op {
+            UnaryOp::Pos => "Pos",
+            UnaryOp::Neg => "Neg",
+            UnaryOp::Fac => "Fac",
+        };
+        json!({
+            "kind": "Unary",
+            "op": op,
+            "span": span(u.span),
+            "value": self.visit_expr(&u.value),
+        })
+    }
+
+    fn visit_binary(&mut self, b: &BinaryArithmetic) -> Value {
+        let op = match b.op {
+            BinaryOp::Add => "Add",
+            BinaryOp::Sub => "Sub",
+            BinaryOp::Mul => "Mul",
+            BinaryOp::Div => "Div",
+            BinaryOp::Mod => "Mod",
+            BinaryOp::FloorDiv => "FloorDiv",
+            BinaryOp::Rem => "Rem",
+            BinaryOp::Pow => "Pow",
+        };
+        json!({
+            "kind": "Binary",
+            "op": op,
+            "span": span(b.span),
+            "lhs": self.visit_expr(&b.lhs),
+            "rhs": self.visit_expr(&b.rhs),
+        })
+    }
+
+    fn visit_function(&mut self, f: &FunctionCall) -> Value {
+        let args: Vec<_> = f.args.iter().map(|arg| self.visit_expr(arg)).collect();
+        json!({
+            "kind": "Call",
+            "span": span(f.span),
+            "name": f.name,
+            "args": args,
+        })
+    }
+
+    fn visit_atom(&mut self, a: &Atom, s: Span) -> Value {
+        match a {
+            Atom::Ident(ref id) => json!({"kind": "Identifier", "span": span(s), "name": id}),
+            Atom::Number(ref n) => json!({"kind": "Number", "span": span(s), "value": n}),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::calc_parser;
+
+    #[test]
+    fn json_ast() {
+        let program = calc_parser::program("let x = -2\nf(y) = y!\nf(x) + 1").unwrap();
+        let ast = JsonPrinter::new().program(&program);
+        assert_eq!(
+            ast[0],
+            json!({
+                "kind": "Let",
+                "span": {"start": 0, "end": 10},
+                "name": "x",
+                "value": {
+                    "kind": "Unary",
+                    "op": "Neg",
+                    "span": {"start": 8, "end": 10},
+                    "value": {"kind": "Number", "span": {"start": 9, "end": 10}, "value": 2.0},
+                },
+            })
+        );
+        assert_eq!(ast[1]["kind"], "Function");
+        assert_eq!(ast[1]["params"], json!(["y"]));
+        assert_eq!(ast[1]["body"]["op"], "Fac");
+
+        let sum = &ast[2]["expr"];
+        assert_eq!(sum["op"], "Add");
+        assert_eq!(sum["lhs"]["kind"], "Call");
+        assert_eq!(sum["lhs"]["args"][0]["name"], "x");
+        assert_eq!(sum["span"], json!({"start": 21, "end": 29}));
+    }
+}
@@ -1,11 +1,17 @@
 pub mod base;
+pub mod check;
 pub mod eval;
 #[cfg(feature = "jit")]
 pub mod jit;
+#[cfg(feature = "cli")]
+pub mod json;
 pub mod printer;
 
 pub use base::Visitor;
-pub use eval::Calculator;
+pub use check::ArityChecker;
+pub use eval::{Arity, Calculator, EvalError};
 #[cfg(feature = "jit")]
-pub use jit::CalculatorJIT;
+pub use jit::{CalculatorJIT, Checkpoint};
+#[cfg(feature = "cli")]
+pub use json::JsonPrinter;
 pub use printer::PrettyPrinter;
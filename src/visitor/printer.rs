@@ -1,9 +1,15 @@
 use super::Visitor;
-use crate::ast::{Atom, BinaryArithmetic, BinaryOp, Expr, FunctionCall, UnaryArithmetic, UnaryOp};
+use crate::ast::{
+    Atom, BinaryArithmetic, BinaryOp, Expr, FunctionCall, Span, Stmt, UnaryArithmetic, UnaryOp,
+};
+use std::fmt::Write;
 
+/// Prints the syntax tree one node per line, children indented under their
+/// parent.
 pub struct PrettyPrinter {
     indent_level: u32,
     indent: u32,
+    out: String,
 }
 
 impl PrettyPrinter {
@@ -11,24 +17,53 @@ impl PrettyPrinter {
         PrettyPrinter {
             indent_level: 0,
             indent,
+            out: String::new(),
         }
     }
 
+    /// Takes the tree printed so far.
+    pub fn finish(&mut self) -> String {
+        std::mem::take(&mut self.out)
+    }
+
     fn get_indent(&self) -> usize {
         (self.indent_level * self.indent) as usize
     }
 }
 
 impl Visitor<()> for PrettyPrinter {
+    fn visit_stmt(&mut self, s: &Stmt) {
+        let indent = " ".repeat(self.get_indent());
+        match s {
+            Stmt::Let(ref a) => writeln!(self.out, "{indent}Let {}", a.name).unwrap(),
+            Stmt::Assign(ref a) => writeln!(self.out, "{indent}Assign {}", a.name).unwrap(),
+            Stmt::Function(ref def) => writeln!(
+                self.out,
+                "{indent}Function {}({})",
+                def.name,
+                def.params.join(", ")
+            )
+            .unwrap(),
+            Stmt::Expr(_) => writeln!(self.out, "{indent}Stmt").unwrap(),
+        }
+        self.indent_level += 1;
+        match s {
+            Stmt::Let(ref a) | Stmt::Assign(ref a) => self.visit_expr(&a.value),
+            Stmt::Function(ref def) => self.visit_expr(&def.body),
+            Stmt::Expr(ref e) => self.visit_expr(e),
+        }
+        self.indent_level -= 1;
+    }
+
     fn visit_expr(&mut self, e: &Expr) {
         let indent = " ".repeat(self.get_indent());
-        println!("{indent}Expr");
+        writeln!(self.out, "{indent}Expr").unwrap();
         self.indent_level += 1;
         match e {
             Expr::UnaryArithmetic(ref u) => self.visit_unary(u),
             Expr::BinaryArithmetic(ref b) => self.visit_binary(b),
             Expr::FunctionCall(ref f) => self.visit_function(f),
-            Expr::Atom(ref a) => self.visit_atom(a),
+            Expr::Atom(ref a, span) => self.visit_atom(a, *span),
         }
         self.indent_level -= 1;
     }
@@ -36,11 +71,11 @@ impl Visitor<()> for PrettyPrinter {
     fn visit_unary(&mut self, u: &UnaryArithmetic) {
         let indent = " ".repeat(self.get_indent());
         match u.op {
-            UnaryOp::Pos => println!("{indent}Pos"),
-            UnaryOp::Neg => println!("{indent}Neg"),
-            UnaryOp::Fac => println!("{indent}Fac"),
+            UnaryOp::Pos => writeln!(self.out, "{indent}Pos").unwrap(),
+            UnaryOp::Neg => writeln!(self.out, "{indent}Neg").unwrap(),
+            UnaryOp::Fac => writeln!(self.out, "{indent}Fac").unwrap(),
         }
-        println!("{indent}Unary");
+        writeln!(self.out, "{indent}Unary").unwrap();
         self.indent_level += 1;
         self.visit_expr(&u.value);
         self.indent_level -= 1;
@@ -49,10 +84,14 @@ impl Visitor<()> for PrettyPrinter {
     fn visit_binary(&mut self, b: &BinaryArithmetic) {
         let indent = " ".repeat(self.get_indent());
         match b.op {
-            BinaryOp::Add => println!("{indent}Add"),
-            BinaryOp::Sub => println!("{indent}Sub"),
-            BinaryOp::Mul => println!("{indent}Mul"),
-            BinaryOp::Div => println!("{indent}Div"),
+            BinaryOp::Add => writeln!(self.out, "{indent}Add").unwrap(),
+            BinaryOp::Sub => writeln!(self.out, "{indent}Sub").unwrap(),
+            BinaryOp::Mul => writeln!(self.out, "{indent}Mul").unwrap(),
+            BinaryOp::Div => writeln!(self.out, "{indent}Div").unwrap(),
+            BinaryOp::Mod => writeln!(self.out, "{indent}Mod").unwrap(),
+            BinaryOp::FloorDiv => writeln!(self.out, "{indent}FloorDiv").unwrap(),
+            BinaryOp::Rem => writeln!(self.out, "{indent}Rem").unwrap(),
+            BinaryOp::Pow => writeln!(self.out, "{indent}Pow").unwrap(),
         }
         self.indent_level += 1;
         self.visit_expr(&b.lhs);
@@ -63,7 +102,7 @@ impl Visitor<()> for PrettyPrinter {
     fn visit_function(&mut self, f: &FunctionCall) {
         let indent = " ".repeat(self.get_indent());
         let func_name = &f.name;
-        println!("{indent}Function {func_name}");
+        writeln!(self.out, "{indent}Function {func_name}").unwrap();
         self.indent_level += 1;
         for arg in &f.args {
             self.visit_expr(arg);
@@ -71,11 +110,11 @@ impl Visitor<()> for PrettyPrinter {
         self.indent_level -= 1;
     }
 
-    fn visit_atom(&mut self, a: &Atom) {
+    fn visit_atom(&mut self, a: &Atom, _span: Span) {
         let indent = " ".repeat(self.get_indent());
         match a {
-            Atom::Ident(ref id) => println!("{indent}Identifier {id}"),
-            Atom::Number(ref n) => println!("{indent}Number {n}"),
+            Atom::Ident(ref id) => writeln!(self.out, "{indent}Identifier {id}").unwrap(),
+            Atom::Number(ref n) => writeln!(self.out, "{indent}Number {n}").unwrap(),
         }
     }
 }
@@ -0,0 +1,258 @@
+//! Runs the `rcalc` binary and checks what it prints and how it exits.
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+const SUCCESS: i32 = 0;
+const PARSE: i32 = 1;
+const EVAL: i32 = 2;
+const BACKEND: i32 = 3;
+const USAGE: i32 = 4;
+
+/// Runs rcalc with `args` and `stdin` as standard input, without colors.
+fn rcalc_with_stdin(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rcalc"))
+        .args(args)
+        .env("NO_COLOR", "1")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn rcalc(args: &[&str]) -> Output {
+    rcalc_with_stdin(args, "")
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8(output.stderr.clone()).unwrap()
+}
+
+/// Checks the exit code, and that stdout is empty and stderr is not unless
+/// the run succeeded.
+#[track_caller]
+fn assert_exit(output: &Output, code: i32) {
+    assert_eq!(output.status.code(), Some(code), "{}", stderr(output));
+    if code == SUCCESS {
+        assert_eq!(stderr(output), "");
+    } else {
+        assert_eq!(stdout(output), "");
+        assert!(stderr(output).starts_with("error"), "{}", stderr(output));
+    }
+}
+
+fn scratch_file(name: &str, content: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rcalc-cli-{}-{name}", std::process::id()));
+    std::fs::write(&path, content).unwrap();
+    path
+}
+
+#[test]
+fn success() {
+    let output = rcalc(&["1 + 2 * 3"]);
+    assert_exit(&output, SUCCESS);
+    assert_eq!(stdout(&output), "Calculator Interpret result: 7\n");
+
+    let output = rcalc(&["-D", "a=1", "-D", "b=-2", "a + b", "--pure"]);
+    assert_exit(&output, SUCCESS);
+    assert_eq!(stdout(&output), "-1");
+
+    let output = rcalc(&["f(x) = x"]);
+    assert_exit(&output, SUCCESS);
+    assert_eq!(stdout(&output), "");
+
+    let output = rcalc(&["--help"]);
+    assert_exit(&output, SUCCESS);
+    assert!(stdout(&output).contains("Exit status:"));
+}
+
+#[test]
+fn parse_errors() {
+    let output = rcalc(&["1 +"]);
+    assert_exit(&output, PARSE);
+    assert!(stderr(&output).contains("unexpected end of input"));
+
+    let output = rcalc_with_stdin(&["-"], "let x = 1\nx * * 2\n");
+    assert_exit(&output, PARSE);
+    assert!(stderr(&output).contains(" --> 2:5\n"));
+}
+
+#[test]
+fn eval_errors() {
+    let output = rcalc(&["unknown + 1"]);
+    assert_exit(&output, EVAL);
+    assert!(stderr(&output).contains("unknown variable `unknown`"));
+
+    let output = rcalc(&["sqr(2)"]);
+    assert_exit(&output, EVAL);
+    assert!(stderr(&output).contains("did you mean `sqrt`?"));
+    let output = rcalc(&["x + 1"]);
+    assert_exit(&output, EVAL);
+    assert!(!stderr(&output).contains("did you mean"));
+
+    assert_exit(&rcalc(&["sin(1, 2)"]), EVAL);
+    assert_exit(&rcalc(&["1 / 0", "--strict"]), EVAL);
+    assert_exit(&rcalc(&["let PI = 3"]), EVAL);
+}
+
+#[test]
+fn backend() {
+    let object = std::env::temp_dir().join(format!("rcalc-cli-{}-f.o", std::process::id()));
+    let args = ["compile", "x + 1", "--params", "x", "--name", "f", "-o"];
+    let output = rcalc(&[&args[..], &[object.to_str().unwrap()]].concat());
+    if cfg!(feature = "jit") {
+        assert_exit(&output, SUCCESS);
+        std::fs::remove_file(&object).unwrap();
+        std::fs::remove_file(object.with_extension("h")).unwrap();
+    } else {
+        assert_exit(&output, BACKEND);
+        assert!(stderr(&output).contains("feature `jit`"));
+    }
+
+    let output = rcalc(&["1 + 2", "--jit"]);
+    if cfg!(feature = "jit") {
+        assert_exit(&output, SUCCESS);
+        assert_eq!(stdout(&output), "JIT compile result: 3\n");
+    } else {
+        assert_exit(&output, BACKEND);
+        assert!(stderr(&output).contains("feature `jit`"));
+    }
+}
+
+#[cfg(feature = "jit")]
+#[test]
+fn jit_errors() {
+    // Names the JIT cannot compile are reported instead of panicking
+    let output = rcalc(&["unknown + sqr(1)", "--jit"]);
+    assert_exit(&output, EVAL);
+    assert!(stderr(&output).contains("unknown variable `unknown`"));
+    assert!(stderr(&output).contains("unknown function `sqr`"));
+
+    assert_exit(&rcalc(&["fac(n) = n * fac(n - 1)", "--jit"]), EVAL);
+    assert_exit(&rcalc(&["sin(1, 2)", "--jit"]), EVAL);
+    assert_exit(&rcalc(&["1 +", "--jit"]), PARSE);
+}
+
+#[test]
+fn usage_errors() {
+    let output = rcalc(&["1", "--no-such-option"]);
+    assert_exit(&output, USAGE);
+
+    let output = rcalc(&["x", "-D", "x=one"]);
+    assert_exit(&output, USAGE);
+    assert!(stderr(&output).contains("not a number"));
+
+    for name in ["let", "rem"] {
+        let output = rcalc(&["1", "-D", &format!("{name}=1")]);
+        assert_exit(&output, USAGE);
+        assert!(stderr(&output).contains("is a keyword"));
+    }
+
+    let output = rcalc(&["-f", "/no/such/script.calc"]);
+    assert_exit(&output, USAGE);
+    assert!(stderr(&output).contains("failed to read"));
+
+    let output = rcalc(&["x", "--vars-file", "/no/such/vars.toml"]);
+    assert_exit(&output, USAGE);
+
+    // The JIT backend would silently divide by zero
+    assert_exit(&rcalc(&["1 / 0", "--strict", "--jit"]), USAGE);
+}
+
+#[test]
+fn script_file() {
+    let path = scratch_file("script.calc", "let r = 2\nf(x) = x * x\nf(r) + 1\n");
+    let output = rcalc(&["-f", path.to_str().unwrap(), "--pure"]);
+    std::fs::remove_file(&path).unwrap();
+    assert_exit(&output, SUCCESS);
+    assert_eq!(stdout(&output), "5");
+}
+
+#[test]
+fn lines() {
+    let input = "1 + 2\n2 +\nsqr(9)\n4\n";
+    let output = rcalc_with_stdin(&["--lines", "--on-error", "marker"], input);
+    assert_eq!(output.status.code(), Some(SUCCESS));
+    assert_eq!(stdout(&output), "3\nerror\nerror\n4\n");
+    assert!(stderr(&output).contains(" --> 2:4\n"));
+    assert!(stderr(&output).contains(" --> 3:1\n"));
+
+    let output = rcalc_with_stdin(&["--lines", "--on-error", "skip"], input);
+    assert_eq!(output.status.code(), Some(SUCCESS));
+    assert_eq!(stdout(&output), "3\n4\n");
+
+    // The exit code is the one of the line that aborted the run
+    let output = rcalc_with_stdin(&["--lines"], input);
+    assert_eq!(output.status.code(), Some(PARSE));
+    assert_eq!(stdout(&output), "3\n");
+    let output = rcalc_with_stdin(&["--lines"], "1\nsqr(9)\n2 +\n");
+    assert_eq!(output.status.code(), Some(EVAL));
+}
+
+#[test]
+fn json() {
+    let output = rcalc(&["1 + 2", "--output", "json"]);
+    assert_eq!(output.status.code(), Some(SUCCESS));
+    let object: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(object["result"], 3.0);
+    assert_eq!(object["success"], true);
+
+    // Errors are on stderr as well as in the object
+    let output = rcalc(&["1 +", "--output", "json"]);
+    assert_eq!(output.status.code(), Some(PARSE));
+    assert!(stderr(&output).contains("unexpected end of input"));
+    let object: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(object["error"]["kind"], "parse");
+
+    let output = rcalc(&["-f", "/no/such/script.calc", "--output", "json"]);
+    assert_eq!(output.status.code(), Some(USAGE));
+    let object: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(object["success"], false);
+
+    // Arguments that clap rejects too
+    let output = rcalc(&["PI", "-D", "PI=3", "--output=json"]);
+    assert_eq!(output.status.code(), Some(USAGE));
+    let object: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(object["error"]["kind"], "usage");
+    assert!(object["error"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("`PI` is a preset constant"));
+}
+
+#[test]
+fn number_format() {
+    // Without formatting options results are printed as they are
+    let output = rcalc(&["10^16", "--pure"]);
+    assert_eq!(stdout(&output), "10000000000000000");
+
+    let output = rcalc(&["2^40", "--format", "eng", "--sig-digits", "4", "--pure"]);
+    assert_exit(&output, SUCCESS);
+    assert_eq!(stdout(&output), "1.100e12");
+
+    let output = rcalc(&["1234567.891", "--group", "comma", "-p", "2", "--pure"]);
+    assert_eq!(stdout(&output), "1,234,567.89");
+
+    // Lines and JSON share the format
+    let output = rcalc_with_stdin(&["--lines", "--non-finite", "js"], "1 / 0\n0 / 0\n");
+    assert_eq!(stdout(&output), "Infinity\nNaN\n");
+    let output = rcalc(&["10^6", "--format", "sci", "--output", "json"]);
+    let object: serde_json::Value = serde_json::from_str(&stdout(&output)).unwrap();
+    assert_eq!(object["formatted"], "1e6");
+
+    assert_exit(&rcalc(&["1", "--format", "exp"]), USAGE);
+    assert_exit(&rcalc(&["1", "-p", "2", "--sig-digits", "2"]), USAGE);
+}